@@ -0,0 +1,428 @@
+use std::{
+    collections::{
+        HashMap,
+        HashSet,
+    },
+    io::Read,
+    path::Path,
+    sync::OnceLock,
+};
+
+use crate::{
+    DEFAULT_DIFFICULTY,
+    Diagnostic,
+    full_title,
+    Map,
+    Metadata,
+    non_empty,
+    Note,
+    ParseError,
+    parse_finite,
+    Sample,
+    sibling_file_name,
+    Warning,
+    timing::BeatTiming,
+};
+
+/// Tempo of charts without a #BPM.
+const DEFAULT_BPM: f64 = 130.0;
+
+/// Player 1's key channels in column order: scratch, keys 1 to 5, keys 6 and
+/// 7 (only in 7 key charts).
+const KEY_CHANNELS: [&str; 8] = ["16", "11", "12", "13", "14", "15", "18", "19"];
+/// Long note channels for `#LNTYPE 1`, in the same order as [`KEY_CHANNELS`].
+const LONG_NOTE_CHANNELS: [&str; 8] = ["56", "51", "52", "53", "54", "55", "58", "59"];
+
+const BGM_CHANNEL: &str = "01";
+const MEASURE_LENGTH_CHANNEL: &str = "02";
+/// BPM as a hex number.
+const BPM_CHANNEL: &str = "03";
+/// BPM from a #BPMxx definition.
+const EXTENDED_BPM_CHANNEL: &str = "08";
+const STOP_CHANNEL: &str = "09";
+
+/// #DIFFICULTY names.
+const DIFFICULTIES: [&str; 5] = ["BEGINNER", "NORMAL", "HYPER", "ANOTHER", "INSANE"];
+/// Overall difficulty for each #RANK, from very hard (0) to easy (3).
+const RANK_OVERALL_DIFFICULTY: [f32; 4] = [9.0, 8.0, 7.0, 6.0];
+
+/// A 2 character object id placed in a measure.
+struct Object {
+    line: usize,
+    measure: usize,
+    /// Where in the measure, from 0 up to 1.
+    position: f64,
+    channel: String,
+    id: String,
+}
+
+/// Parses a .bms, .bme or .bml file. Keysounds become the map's samples, so
+/// it has no audio file.
+pub(crate) fn read<R>(file_name: &str, mut reader: R, warnings: &mut Vec<Diagnostic<Warning>>) -> Result<Map, Diagnostic<ParseError>> where
+    R: Read
+{
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes).map_err(|error| Diagnostic::at(None, error.into()))?;
+    // Most charts are Shift-JIS
+    let text = String::from_utf8_lossy(&bytes);
+
+    let mut title = None;
+    let mut subtitle = None;
+    let mut artist = String::new();
+    let mut subartist = String::new();
+    let mut genre = String::new();
+    let mut play_level = String::new();
+    let mut difficulty = None;
+    let mut rank = None;
+    let mut bpm = DEFAULT_BPM;
+    let mut long_note_object = None;
+    let mut wavs: HashMap<String, String> = HashMap::new();
+    let mut bpm_definitions: HashMap<String, f64> = HashMap::new();
+    let mut stop_definitions: HashMap<String, f64> = HashMap::new();
+    let mut measure_lengths: HashMap<usize, f64> = HashMap::new();
+    let mut objects = Vec::new();
+    // #RANDOM always picks 1, so the same chart loads every time
+    let mut randoms: Vec<u32> = Vec::new();
+    // Whether each #IF being read is skipped
+    let mut skipping: Vec<bool> = Vec::new();
+
+    for (index, line) in text.lines().enumerate() {
+        let line_number = index+1;
+        let line = match line.trim().strip_prefix('#') {
+            Some(line) => line,
+            // Anything else is a comment
+            None => continue,
+        };
+        let (command, value) = match line.split_once(|character: char| character.is_whitespace()) {
+            Some((command, value)) => (command.to_ascii_uppercase(), value.trim()),
+            None => (line.to_ascii_uppercase(), ""),
+        };
+        match command.as_str() {
+            "RANDOM" | "SETRANDOM" => {
+                randoms.push(if command == "RANDOM" { 1 } else { value.parse().unwrap_or(1) });
+                continue;
+            },
+            "ENDRANDOM" => {
+                randoms.pop();
+                continue;
+            },
+            "IF" => {
+                let branch = value.parse::<u32>().ok();
+                skipping.push(branch.is_none() || branch != randoms.last().copied());
+                continue;
+            },
+            "ELSE" => {
+                if let Some(skip) = skipping.last_mut() {
+                    *skip = !*skip;
+                }
+                continue;
+            },
+            "ENDIF" | "END" => {
+                skipping.pop();
+                continue;
+            },
+            _ => {},
+        }
+        if skipping.iter().any(|skip| *skip) {
+            continue;
+        }
+
+        // #mmmcc:data
+        if let Some((channel, data)) = line.split_once(':') {
+            if channel.len() == 5 && channel.is_ascii() {
+                if let Ok(measure) = channel[..3].parse::<usize>() {
+                    let channel = channel[3..].to_ascii_uppercase();
+                    let data = data.trim();
+                    if channel == MEASURE_LENGTH_CHANNEL {
+                        let length = parse_finite("measure length", data).map_err(|error| Diagnostic::at(Some(line_number), error))?;
+                        measure_lengths.insert(measure, length);
+                        continue;
+                    }
+                    let ids: Vec<&str> = data.as_bytes()
+                        .chunks(2)
+                        .filter_map(|id| std::str::from_utf8(id).ok())
+                        .collect();
+                    for (position, id) in ids.iter().enumerate() {
+                        if *id == "00" {
+                            continue;
+                        }
+                        objects.push(Object {
+                            line: line_number,
+                            measure,
+                            position: position as f64/ids.len() as f64,
+                            channel: channel.clone(),
+                            id: id.to_ascii_uppercase(),
+                        });
+                    }
+                    continue;
+                }
+            }
+        }
+
+        let definition = |name: &str| command.strip_prefix(name).filter(|id| id.len() == 2).map(str::to_string);
+        match command.as_str() {
+            "TITLE" => title = non_empty(value),
+            "SUBTITLE" => subtitle = non_empty(value),
+            "ARTIST" => artist = value.to_string(),
+            "SUBARTIST" => subartist = value.to_string(),
+            "GENRE" => genre = value.to_string(),
+            "PLAYLEVEL" => play_level = value.to_string(),
+            "DIFFICULTY" => difficulty = value.parse::<usize>().ok(),
+            "RANK" => rank = value.parse::<usize>().ok(),
+            "BPM" => bpm = parse_finite("BPM", value).map_err(|error| Diagnostic::at(Some(line_number), error))?,
+            "LNOBJ" => long_note_object = Some(value.to_ascii_uppercase()),
+            // Images, videos, gauges, ... are left out
+            _ => if let Some(id) = definition("WAV") {
+                wavs.insert(id, sibling_file_name(file_name, &value.replace('\\', "/")));
+            } else if let Some(id) = definition("BPM") {
+                bpm_definitions.insert(id, parse_finite("BPM", value).map_err(|error| Diagnostic::at(Some(line_number), error))?);
+            } else if let Some(id) = definition("STOP") {
+                stop_definitions.insert(id, parse_finite("STOP", value).map_err(|error| Diagnostic::at(Some(line_number), error))?);
+            },
+        }
+    }
+
+    let title = title.ok_or_else(|| Diagnostic::at(None, ParseError::MissingTitle))?;
+    let title = match subtitle {
+        Some(subtitle) => format!("{} {}", title, subtitle),
+        None => title,
+    };
+    if bpm <= 0.0 {
+        return Err(Diagnostic::at(None, ParseError::InvalidNumber {
+            field: "BPM",
+            value: bpm.to_string(),
+        }));
+    }
+
+    // Start of each measure in beats, with one past the last for objects at
+    // its very end
+    let measure_count = objects.iter().map(|object| object.measure+1).max().unwrap_or(0);
+    let mut measure_starts = vec![0.0];
+    for measure in 0..measure_count {
+        let length = measure_lengths.get(&measure).copied().unwrap_or(1.0);
+        measure_starts.push(measure_starts[measure] + length*4.0);
+    }
+    let beat = |object: &Object| {
+        let start = measure_starts[object.measure];
+        start + object.position*(measure_starts[object.measure+1] - start)
+    };
+    objects.sort_by(|a, b| beat(a).total_cmp(&beat(b)));
+
+    let mut warn = |line, reason| warnings.push(Diagnostic::at(Some(line), reason));
+
+    let mut timing = BeatTiming {
+        offset: 0.0,
+        bpms: vec![(0.0, bpm)],
+        stops: Vec::new(),
+        delays: Vec::new(),
+    };
+    for object in objects.iter() {
+        let change = match object.channel.as_str() {
+            BPM_CHANNEL => u8::from_str_radix(&object.id, 16).ok().map(f64::from),
+            EXTENDED_BPM_CHANNEL => bpm_definitions.get(&object.id).copied(),
+            _ => continue,
+        };
+        match change {
+            Some(bpm) if bpm > 0.0 => timing.bpms.push((beat(object), bpm)),
+            _ => warn(object.line, Warning::UndefinedObject(format!("#BPM{}", object.id))),
+        }
+    }
+    for object in objects.iter().filter(|object| object.channel == STOP_CHANNEL) {
+        match stop_definitions.get(&object.id) {
+            // Stops are in 192nds of a 4/4 measure
+            Some(length) => {
+                let beat = beat(object);
+                timing.stops.push((beat, length/48.0*60_000.0/timing.bpm_at(beat)));
+            },
+            None => warn(object.line, Warning::UndefinedObject(format!("#STOP{}", object.id))),
+        }
+    }
+
+    // Leave out scratch and keys 6 and 7 when the chart doesn't use them
+    let used = |index: usize| objects.iter().any(|object| object.channel == KEY_CHANNELS[index] || object.channel == LONG_NOTE_CHANNELS[index]);
+    let mut channels = Vec::new();
+    if used(0) {
+        channels.push(0);
+    }
+    channels.extend(1..=5);
+    if used(6) || used(7) {
+        channels.extend(6..=7);
+    }
+    let column_count = channels.len();
+
+    let mut notes = vec![Vec::new(); column_count];
+    let mut samples = Vec::new();
+    // Start times of #LNTYPE 1 long notes waiting for their end
+    let mut holds: Vec<Option<usize>> = vec![None; column_count];
+    let mut unsupported_channels = HashSet::new();
+    for object in objects.iter() {
+        let time = timing.time_at(beat(object)).round() as usize;
+        let key = channels.iter().position(|channel| KEY_CHANNELS[*channel] == object.channel);
+        let long_note = channels.iter().position(|channel| LONG_NOTE_CHANNELS[*channel] == object.channel);
+        let keysound = match (key, long_note) {
+            _ if object.channel == BGM_CHANNEL => true,
+            (Some(column), _) if long_note_object.as_ref() == Some(&object.id) => {
+                match notes[column].last_mut() {
+                    Some(Note { end_time: end_time @ None, .. }) => *end_time = Some(time),
+                    _ => warn(object.line, Warning::UnmatchedHoldTail),
+                }
+                false
+            },
+            (Some(column), _) => {
+                notes[column].push(Note {
+                    time,
+                    end_time: None,
+                });
+                true
+            },
+            (_, Some(column)) => match holds[column].take() {
+                Some(start) => {
+                    notes[column].push(Note {
+                        time: start,
+                        end_time: Some(time),
+                    });
+                    false
+                },
+                None => {
+                    holds[column] = Some(time);
+                    true
+                }
+            },
+            _ => {
+                // Player 2's keys and the foot pedal. Invisible notes, mines
+                // and images are left out without a warning.
+                let playable = matches!(object.channel.as_bytes(), [b'2' | b'6', b'1'..=b'9']) || object.channel == "17" || object.channel == "57";
+                if playable && unsupported_channels.insert(object.channel.clone()) {
+                    warn(object.line, Warning::UnsupportedChannel(object.channel.clone()));
+                }
+                false
+            }
+        };
+        if keysound {
+            if let Some(wav) = wavs.get(&object.id) {
+                samples.push(Sample {
+                    time,
+                    file_name: wav.clone(),
+                });
+            }
+        }
+    }
+    if holds.iter().any(Option::is_some) {
+        warnings.push(Diagnostic::at(None, Warning::UnterminatedHold));
+    }
+    for column in notes.iter_mut() {
+        column.sort_by_key(|note| note.time);
+    }
+
+    let version = match difficulty.and_then(|difficulty| DIFFICULTIES.get(difficulty.wrapping_sub(1))) {
+        Some(difficulty) if play_level.is_empty() => difficulty.to_string(),
+        Some(difficulty) => format!("{} {}", difficulty, play_level),
+        None if play_level.is_empty() => Path::new(file_name).file_stem().map_or_else(String::new, |stem| stem.to_string_lossy().into_owned()),
+        None => play_level,
+    };
+
+    Ok(Map {
+        format_version: 0,
+        audio_file_name: String::new(),
+        audio_lead_in: 0,
+        full_title: full_title(&version, &title),
+        metadata: Metadata {
+            title: title.clone(),
+            title_unicode: title,
+            artist: artist.clone(),
+            artist_unicode: artist,
+            creator: subartist,
+            version,
+            source: String::new(),
+            tags: genre.split_whitespace().map(str::to_string).collect(),
+            beatmap_id: None,
+            beatmap_set_id: None,
+        },
+        column_count,
+        hp_drain_rate: DEFAULT_DIFFICULTY,
+        overall_difficulty: rank.and_then(|rank| RANK_OVERALL_DIFFICULTY.get(rank).copied()).unwrap_or(DEFAULT_DIFFICULTY),
+        notes,
+        timing_points: timing.timing_points(4),
+        samples,
+        checksum: String::new(),
+        audio_checksum: OnceLock::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FileReport;
+
+    /// 120 BPM, so a beat is 500ms and a 4/4 measure 2000ms. Measure 1 is
+    /// half as long, and measure 2 starts with a stop of 96/192 of a
+    /// measure.
+    const CHART: &str = "#TITLE Song
+#BPM 120
+#WAV01 kick.wav
+#STOP01 96
+#LNOBJ ZZ
+#00011:01
+#00102:0.5
+#00111:0101
+#00209:01
+#00212:01
+#00213:0001
+#00314:01ZZ
+#RANDOM 2
+#IF 1
+#00015:01
+#ENDIF
+#IF 2
+#00015:0001
+#ENDIF
+#ENDRANDOM
+";
+
+    fn parse(chart: &str) -> Map {
+        let (map, report) = FileReport::parse("song/hard.bms", chart.as_bytes(), read);
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+        map.unwrap()
+    }
+
+    fn times(notes: &[Note]) -> Vec<usize> {
+        notes.iter().map(|note| note.time).collect()
+    }
+
+    #[test]
+    fn measure_lengths_and_stops_move_notes() {
+        let map = parse(CHART);
+        // Measure 1 is 2 beats, so measure 2 starts on beat 6
+        assert_eq!(times(&map.notes[0]), [0, 2000, 2500]);
+        // The stop comes after the notes on its own beat, and lasts 1000ms
+        assert_eq!(times(&map.notes[1]), [3000]);
+        assert_eq!(times(&map.notes[2]), [5000]);
+        assert_eq!(map.samples.first().map(|sample| sample.file_name.as_str()), Some("song/kick.wav"));
+    }
+
+    #[test]
+    fn lnobj_ends_the_note_before_it() {
+        let map = parse(CHART);
+        assert_eq!(map.notes[3], vec![Note {
+            time: 6000,
+            end_time: Some(7000),
+        }]);
+    }
+
+    #[test]
+    fn random_always_takes_the_first_branch() {
+        let map = parse(CHART);
+        assert_eq!(times(&map.notes[4]), [0]);
+    }
+
+    #[test]
+    fn columns_follow_the_keys_used() {
+        assert_eq!(parse(CHART).column_count, 5);
+        let seven_keys = format!("{}#00018:01\n", CHART);
+        assert_eq!(parse(&seven_keys).column_count, 7);
+        // Scratch is the first column
+        let map = parse(&format!("{}#00016:0001\n", seven_keys));
+        assert_eq!(map.column_count, 8);
+        assert_eq!(times(&map.notes[0]), [1000]);
+    }
+}
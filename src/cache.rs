@@ -0,0 +1,327 @@
+use std::{
+    collections::{
+        BTreeMap,
+        HashMap,
+    },
+    fs::File,
+    io::{
+        BufReader,
+        BufWriter,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+    sync::{
+        Mutex,
+        MutexGuard,
+    },
+    time::{
+        Duration,
+        SystemTime,
+    },
+};
+
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::{
+    depot,
+    files,
+    parse_chart,
+    Diagnostic,
+    FileReport,
+    FileStore,
+    LoadError,
+    Map,
+    ParseReport,
+    Set,
+    Warning,
+};
+
+/// Bumped whenever what's cached (or what the parsers make of a chart)
+/// changes, so old caches are thrown out instead of misread.
+const CACHE_VERSION: u32 = 6;
+
+/// Sets parsed on earlier runs, kept in a file so that only sets that are
+/// new or have changed since need parsing. A set counts as changed when its
+/// size or modification time is different (for directories, the total size
+/// and latest modification time of everything in them).
+pub struct DepotCache {
+    path: PathBuf,
+    /// What was in the cache file.
+    previous: HashMap<PathBuf, CachedSet>,
+    /// Sets loaded since opening, which is what gets saved. Locked so sets
+    /// can be loaded from several threads.
+    loaded: Mutex<BTreeMap<PathBuf, CachedSet>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheFile {
+    version: u32,
+    /// Read one at a time, so one bad set doesn't lose the whole cache.
+    sets: Vec<serde_json::Value>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct CachedSet {
+    path: PathBuf,
+    stamp: Stamp,
+    maps: Vec<Map>,
+    /// The set's files that aren't charts.
+    names: Vec<String>,
+    reports: Vec<CachedReport>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct Stamp {
+    size: u64,
+    /// Since the Unix epoch.
+    modified: Duration,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct CachedReport {
+    file_name: String,
+    warnings: Vec<Diagnostic<Warning>>,
+    /// Rejections can hold I/O errors, which can't be saved, so rejected
+    /// charts are parsed again to get them back.
+    rejected: bool,
+}
+
+impl DepotCache {
+    /// Reads the cache file at `path`. A missing, unreadable or outdated file
+    /// gives an empty cache.
+    pub fn open<P>(path: P) -> DepotCache where
+        P: AsRef<Path>
+    {
+        let file: Option<CacheFile> = File::open(path.as_ref())
+            .ok()
+            .and_then(|file| serde_json::from_reader(BufReader::new(file)).ok())
+            .filter(|file: &CacheFile| file.version == CACHE_VERSION);
+        // Sets with values JSON can't hold (like infinite beat lengths) don't
+        // read back, and get parsed again
+        let previous = file.map_or_else(Vec::new, |file| file.sets)
+            .into_iter()
+            .filter_map(|set| serde_json::from_value::<CachedSet>(set).ok())
+            .map(|set| (set.path.clone(), set))
+            .collect();
+        DepotCache {
+            path: path.as_ref().to_path_buf(),
+            previous,
+            loaded: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Loads the set at `path`, either an archive like [`Set::from_osz`] or a
+    /// directory like [`Set::from_dir`]. Comes from the cache when the set
+    /// hasn't changed, and is parsed and cached otherwise.
+    pub fn load<P>(&self, path: P) -> Result<Set, LoadError> where
+        P: AsRef<Path>
+    {
+        let path = path.as_ref();
+        let stamp = Stamp::of(path)?;
+        if let Some(cached) = self.previous.get(path).filter(|cached| cached.stamp == stamp) {
+            let set = cached.to_set()?;
+            self.loaded().insert(path.to_path_buf(), cached.clone());
+            return Ok(set);
+        }
+
+        let set = depot::load_set(path)?;
+        self.loaded().insert(path.to_path_buf(), CachedSet {
+            path: path.to_path_buf(),
+            stamp,
+            maps: set.maps.clone(),
+            names: set.files.names().map(str::to_string).collect(),
+            reports: set.report.files.iter()
+                .map(|file| CachedReport {
+                    file_name: file.file_name.clone(),
+                    warnings: file.warnings.clone(),
+                    rejected: file.rejection.is_some(),
+                })
+                .collect(),
+        });
+        Ok(set)
+    }
+
+    /// Writes every set loaded since opening to the cache file. Sets that
+    /// weren't loaded, like ones taken out of the depot, are dropped.
+    pub fn save(&self) -> Result<(), LoadError> {
+        let file = CacheFile {
+            version: CACHE_VERSION,
+            sets: self.loaded().values()
+                .map(serde_json::to_value)
+                .collect::<Result<_, _>>()
+                .map_err(std::io::Error::from)?,
+        };
+        // Written next to the cache first, so a crash can't leave half a file
+        let temporary_path = self.path.with_extension("tmp");
+        serde_json::to_writer(BufWriter::new(File::create(&temporary_path)?), &file)
+            .map_err(std::io::Error::from)?;
+        std::fs::rename(temporary_path, &self.path)?;
+        Ok(())
+    }
+
+    fn loaded(&self) -> MutexGuard<'_, BTreeMap<PathBuf, CachedSet>> {
+        self.loaded.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl CachedSet {
+    fn to_set(&self) -> Result<Set, LoadError> {
+        let mut report = ParseReport::default();
+        let files = if self.path.is_dir() {
+            for file in self.reports.iter() {
+                if file.rejected {
+                    parse_chart(&file.file_name, File::open(self.path.join(&file.file_name))?, &mut Vec::new(), &mut report);
+                } else {
+                    report.files.push(file.to_report());
+                }
+            }
+            FileStore::directory(self.path.clone(), self.names.clone())
+        } else {
+            let mut archive = files::open_archive(File::open(&self.path)?)?;
+            for file in self.reports.iter() {
+                if file.rejected {
+                    parse_chart(&file.file_name, archive.by_name(&file.file_name)?, &mut Vec::new(), &mut report);
+                } else {
+                    report.files.push(file.to_report());
+                }
+            }
+            FileStore::archive(archive, self.names.clone())
+        };
+        Ok(Set {
+            maps: self.maps.clone(),
+            files,
+            report,
+        })
+    }
+}
+
+impl CachedReport {
+    fn to_report(&self) -> FileReport {
+        FileReport {
+            file_name: self.file_name.clone(),
+            warnings: self.warnings.clone(),
+            rejection: None,
+        }
+    }
+}
+
+impl Stamp {
+    fn of(path: &Path) -> std::io::Result<Stamp> {
+        let metadata = std::fs::metadata(path)?;
+        let mut stamp = Stamp {
+            size: 0,
+            modified: since_epoch(metadata.modified()?),
+        };
+        if !metadata.is_dir() {
+            stamp.size = metadata.len();
+            return Ok(stamp);
+        }
+        // A directory's own time only changes when files are added or
+        // removed, not when one is edited
+        let mut directories = vec![path.to_path_buf()];
+        while let Some(directory) = directories.pop() {
+            for entry in std::fs::read_dir(directory)? {
+                let entry = entry?;
+                let metadata = entry.metadata()?;
+                stamp.modified = stamp.modified.max(since_epoch(metadata.modified()?));
+                if metadata.is_dir() {
+                    directories.push(entry.path());
+                } else {
+                    stamp.size += metadata.len();
+                }
+            }
+        }
+        Ok(stamp)
+    }
+}
+
+fn since_epoch(time: SystemTime) -> Duration {
+    time.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ParseError;
+
+    const OSU: &str = "osu file format v14
+[General]
+AudioFilename: audio.mp3
+Mode: 3
+[Metadata]
+Title:Song
+Version:Hard
+[Difficulty]
+CircleSize:4
+[TimingPoints]
+0,500,4,2,0,60,1,0
+[HitObjects]
+64,192,500,1,0,0:0:0:0:
+";
+
+    /// An empty directory for `test` to work in.
+    fn temporary_directory(test: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("micro_vsrg-{}-{}", test, std::process::id()));
+        std::fs::remove_dir_all(&path).ok();
+        std::fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    /// A set directory with a good and a bad chart, and a cache that has
+    /// loaded it.
+    fn cached_set(directory: &Path) -> (PathBuf, PathBuf) {
+        let set = directory.join("set");
+        std::fs::create_dir(&set).unwrap();
+        std::fs::write(set.join("good.osu"), OSU).unwrap();
+        std::fs::write(set.join("bad.osu"), OSU.replace("CircleSize:4", "CircleSize:four")).unwrap();
+        let cache_path = directory.join("depot.cache");
+        let cache = DepotCache::open(&cache_path);
+        cache.load(&set).unwrap();
+        cache.save().unwrap();
+        (set, cache_path)
+    }
+
+    /// Changes the cache file at `path` with `edit`.
+    fn edit_cache<F>(path: &Path, edit: F) where
+        F: FnOnce(&mut serde_json::Value)
+    {
+        let mut file: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+        edit(&mut file);
+        std::fs::write(path, file.to_string()).unwrap();
+    }
+
+    #[test]
+    fn outdated_caches_are_thrown_away() {
+        let directory = temporary_directory("outdated");
+        let (set, cache_path) = cached_set(&directory);
+        assert!(DepotCache::open(&cache_path).previous.contains_key(&set));
+
+        edit_cache(&cache_path, |file| file["version"] = (CACHE_VERSION-1).into());
+        assert!(DepotCache::open(&cache_path).previous.is_empty());
+        std::fs::remove_dir_all(directory).ok();
+    }
+
+    #[test]
+    fn rejected_files_are_parsed_again() {
+        let directory = temporary_directory("rejected");
+        let (set, cache_path) = cached_set(&directory);
+        // Only a set that came from the cache has this title
+        edit_cache(&cache_path, |file| file["sets"][0]["maps"][0]["metadata"]["title"] = "Cached".into());
+
+        let set = DepotCache::open(&cache_path).load(&set).unwrap();
+        assert_eq!(set.maps.len(), 1);
+        assert_eq!(set.maps[0].metadata.title, "Cached");
+        let rejected: Vec<&FileReport> = set.report.rejected().collect();
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(rejected[0].file_name, "bad.osu");
+        let rejection = rejected[0].rejection.as_ref().unwrap();
+        assert!(matches!(rejection.reason, ParseError::UnsupportedColumnCount(ref count) if count == "four"));
+        assert_eq!(rejection.line, Some(9));
+        std::fs::remove_dir_all(directory).ok();
+    }
+}
@@ -0,0 +1,152 @@
+use crate::{
+    full_title,
+    Map,
+    MAX_COLUMN_COUNT,
+    Note,
+};
+
+/// How [`Map::convert_columns`] fits a map into fewer columns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnStrategy {
+    /// Merges neighbouring columns, so 8K folds in pairs and 7K folds
+    /// `[0, 1] [2, 3] [4, 5] [6]`.
+    Fold,
+    /// Keeps evenly spaced columns and drops the rest, like the middle
+    /// column of 5K.
+    Drop,
+    /// Moves each note to the nearest column that's free, keeping chords,
+    /// jacks and stairs the way they were.
+    Remap,
+}
+
+impl Map {
+    /// A copy of the map with `column_count` columns. Fewer columns use
+    /// `strategy`, more columns spread each column's notes over the ones that
+    /// replace it (4K to 7K turns a column into 1 or 2 columns). Notes that
+    /// end up on top of each other, or inside a hold, are dropped.
+    ///
+    /// `None` when `column_count` isn't between 1 and [`MAX_COLUMN_COUNT`].
+    pub fn convert_columns(&self, column_count: usize, strategy: ColumnStrategy) -> Option<Map> {
+        if column_count == 0 || column_count > MAX_COLUMN_COUNT {
+            return None;
+        }
+        if column_count == self.column_count {
+            return Some(self.clone());
+        }
+        let notes = if column_count > self.column_count {
+            self.expand(column_count)
+        } else {
+            match strategy {
+                ColumnStrategy::Fold => self.move_columns(column_count, |column| Some(column*column_count/self.column_count)),
+                ColumnStrategy::Drop => {
+                    let kept = kept_columns(self.column_count, column_count);
+                    self.move_columns(column_count, |column| kept.iter().position(|kept| *kept == column))
+                },
+                ColumnStrategy::Remap => self.remap(column_count),
+            }
+        };
+        let mut map = self.clone();
+        map.metadata.version = format!("{} ({}K)", self.metadata.version, column_count);
+        map.full_title = full_title(&map.metadata.version, &map.metadata.title_unicode);
+        map.column_count = column_count;
+        map.notes = notes;
+        Some(map)
+    }
+
+    /// Moves every note by `to_column`, dropping the ones it gives `None` for.
+    fn move_columns<F>(&self, column_count: usize, to_column: F) -> Vec<Vec<Note>> where
+        F: Fn(usize) -> Option<usize>
+    {
+        let mut notes = vec![Vec::new(); column_count];
+        for (column, column_notes) in self.notes.iter().enumerate() {
+            if let Some(column) = to_column(column) {
+                notes[column].extend(column_notes.iter().copied());
+            }
+        }
+        for column in notes.iter_mut() {
+            deduplicate(column);
+        }
+        notes
+    }
+
+    fn remap(&self, column_count: usize) -> Vec<Vec<Note>> {
+        let mut notes = vec![Vec::new(); column_count];
+        // Original and new column of each note in the row before
+        let mut previous_row: Vec<(usize, usize)> = Vec::new();
+        for row in self.note_rows() {
+            let mut targets: Vec<(usize, usize)> = Vec::new();
+            for (column, note) in row.iter().copied() {
+                let jack = previous_row.iter().find(|(previous, _)| *previous == column).map(|(_, target)| *target);
+                // The closest note before decides which way this one moves
+                let closest = previous_row.iter().min_by_key(|(previous, _)| previous.abs_diff(column)).copied();
+                let preferred = jack.unwrap_or(column*column_count/self.column_count);
+                let target = (0..column_count)
+                    .filter(|target| !targets.iter().any(|(_, used)| used == target) && is_free(&notes[*target], note.time))
+                    .min_by_key(|target| {
+                        // Only jacks in the original map should be jacks after
+                        let new_jack = jack.is_none() && previous_row.iter().any(|(_, previous)| previous == target);
+                        let turned = closest.is_some_and(|(previous, previous_target)| jack.is_none() && column.cmp(&previous) != target.cmp(&previous_target));
+                        (new_jack, turned, target.abs_diff(preferred), *target)
+                    });
+                if let Some(target) = target {
+                    notes[target].push(note);
+                    targets.push((column, target));
+                }
+            }
+            previous_row = targets;
+        }
+        notes
+    }
+
+    /// Spreads each column over the columns that replace it, taking turns
+    /// row by row so patterns grow with the extra columns.
+    fn expand(&self, column_count: usize) -> Vec<Vec<Note>> {
+        let mut notes = vec![Vec::new(); column_count];
+        for (index, row) in self.note_rows().iter().enumerate() {
+            let mut targets = Vec::new();
+            for (column, note) in row.iter().copied() {
+                let start = column*column_count/self.column_count;
+                let end = (column+1)*column_count/self.column_count;
+                let group: Vec<usize> = (start..end).collect();
+                let turn = index % group.len();
+                let target = group[turn..].iter().chain(group[..turn].iter())
+                    .copied()
+                    .chain((0..column_count).filter(|target| !group.contains(target)))
+                    .find(|target| !targets.contains(target) && is_free(&notes[*target], note.time));
+                if let Some(target) = target {
+                    notes[target].push(note);
+                    targets.push(target);
+                }
+            }
+        }
+        notes
+    }
+}
+
+/// `count` evenly spaced columns out of `column_count`.
+fn kept_columns(column_count: usize, count: usize) -> Vec<usize> {
+    if count == 1 {
+        return vec![column_count/2];
+    }
+    (0..count)
+        .map(|index| ((index*(column_count-1)) as f64/(count-1) as f64).round() as usize)
+        .collect()
+}
+
+/// Whether a note at `time` can go after the last note in `column`.
+fn is_free(column: &[Note], time: usize) -> bool {
+    column.last().is_none_or(|last| last.end_time.unwrap_or(last.time) < time)
+}
+
+/// Sorts a column and drops notes that start on or inside the note before
+/// them. Of notes at the same time, the longest is kept.
+fn deduplicate(column: &mut Vec<Note>) {
+    column.sort_by_key(|note| (note.time, std::cmp::Reverse(note.end_time)));
+    let mut kept: Vec<Note> = Vec::with_capacity(column.len());
+    for note in column.drain(..) {
+        if is_free(&kept, note.time) {
+            kept.push(note);
+        }
+    }
+    *column = kept;
+}
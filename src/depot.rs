@@ -0,0 +1,173 @@
+use std::{
+    fs::File,
+    num::NonZeroUsize,
+    path::{
+        Path,
+        PathBuf,
+    },
+    sync::atomic::{
+        AtomicUsize,
+        Ordering,
+    },
+};
+
+use crate::{
+    DepotCache,
+    LoadError,
+    Set,
+};
+
+/// Extensions of the zipped sets a depot can hold, next to extracted sets in
+/// directories.
+pub const ARCHIVE_EXTENSIONS: [&str; 3] = ["osz", "qp", "mcz"];
+
+/// Every set in a depot directory, see [`Depot::load`].
+#[derive(Debug)]
+pub struct Depot {
+    /// Sorted by path.
+    pub sets: Vec<Set>,
+    /// Where each of `sets` was loaded from.
+    pub paths: Vec<PathBuf>,
+    /// Sets that couldn't be loaded, sorted by path.
+    pub errors: Vec<(PathBuf, LoadError)>,
+}
+
+impl Depot {
+    /// Loads every archive (see [`ARCHIVE_EXTENSIONS`]) and directory in
+    /// `path` as a set, on one thread per core. Sets come out in the same
+    /// order however long each takes. A set failing to load doesn't stop the
+    /// rest, only failing to list `path` does.
+    pub fn load<P>(path: P) -> Result<Depot, LoadError> where
+        P: AsRef<Path>
+    {
+        Depot::load_with(path.as_ref(), load_set)
+    }
+
+    /// Like [`Depot::load`], but sets that haven't changed come from `cache`.
+    pub fn load_cached<P>(path: P, cache: &DepotCache) -> Result<Depot, LoadError> where
+        P: AsRef<Path>
+    {
+        Depot::load_with(path.as_ref(), |path| cache.load(path))
+    }
+
+    fn load_with<F>(path: &Path, load: F) -> Result<Depot, LoadError> where
+        F: Fn(&Path) -> Result<Set, LoadError> + Sync
+    {
+        let mut paths = Vec::new();
+        for entry in std::fs::read_dir(path)? {
+            let path = entry?.path();
+            if path.is_dir() || is_archive(&path) {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+
+        let thread_count = std::thread::available_parallelism()
+            .map_or(1, NonZeroUsize::get)
+            .min(paths.len());
+        // Threads take the next set when they finish one, so a big set
+        // doesn't hold up the ones after it
+        let next = AtomicUsize::new(0);
+        let mut results: Vec<(usize, Result<Set, LoadError>)> = std::thread::scope(|scope| {
+            let threads: Vec<_> = (0..thread_count)
+                .map(|_| scope.spawn(|| {
+                    let mut results = Vec::new();
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(path) = paths.get(index) else {
+                            return results;
+                        };
+                        results.push((index, load(path)));
+                    }
+                }))
+                .collect();
+            threads.into_iter()
+                .flat_map(|thread| thread.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic)))
+                .collect()
+        });
+        results.sort_by_key(|(index, _)| *index);
+
+        let mut depot = Depot {
+            sets: Vec::new(),
+            paths: Vec::new(),
+            errors: Vec::new(),
+        };
+        for ((_, result), path) in results.into_iter().zip(paths) {
+            match result {
+                Ok(set) => {
+                    depot.sets.push(set);
+                    depot.paths.push(path);
+                },
+                Err(error) => depot.errors.push((path, error)),
+            }
+        }
+        Ok(depot)
+    }
+}
+
+fn is_archive(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| ARCHIVE_EXTENSIONS.iter().any(|archive| extension.eq_ignore_ascii_case(archive)))
+}
+
+/// Loads the set at `path`, which is a directory or an archive.
+pub(crate) fn load_set(path: &Path) -> Result<Set, LoadError> {
+    if path.is_dir() {
+        Set::from_dir(path)
+    } else {
+        Set::from_osz(File::open(path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::Mutex,
+        time::Duration,
+    };
+
+    use super::*;
+
+    #[test]
+    fn loads_every_set_once_in_path_order() {
+        let directory = std::env::temp_dir().join(format!("micro_vsrg-depot-{}", std::process::id()));
+        std::fs::remove_dir_all(&directory).ok();
+        std::fs::create_dir_all(&directory).unwrap();
+        let names: Vec<String> = (0..12).map(|index| format!("set {:02}", index)).collect();
+        for name in names.iter() {
+            std::fs::create_dir(directory.join(name)).unwrap();
+            std::fs::write(directory.join(name).join("map.osu"), format!("osu file format v14
+[General]
+AudioFilename: audio.mp3
+Mode: 3
+[Metadata]
+Title:{}
+Version:Hard
+[Difficulty]
+CircleSize:4
+", name)).unwrap();
+        }
+        std::fs::write(directory.join("broken.osz"), "not a zip").unwrap();
+        std::fs::write(directory.join("readme.txt"), "not a set").unwrap();
+
+        let loads: Mutex<HashMap<PathBuf, usize>> = Mutex::new(HashMap::new());
+        let depot = Depot::load_with(&directory, |path| {
+            *loads.lock().unwrap().entry(path.to_path_buf()).or_default() += 1;
+            // Earlier sets finish last
+            let name = path.file_name().unwrap().to_string_lossy();
+            let index: u64 = name.trim_start_matches("set ").parse().unwrap_or(0);
+            std::thread::sleep(Duration::from_millis(12 - index));
+            load_set(path)
+        }).unwrap();
+
+        let loads = loads.into_inner().unwrap();
+        assert_eq!(loads.len(), names.len()+1);
+        assert!(loads.values().all(|count| *count == 1));
+        let titles: Vec<&str> = depot.sets.iter().map(|set| set.maps[0].metadata.title.as_str()).collect();
+        assert_eq!(titles, names);
+        assert_eq!(depot.paths, names.iter().map(|name| directory.join(name)).collect::<Vec<_>>());
+        assert_eq!(depot.errors.len(), 1);
+        assert_eq!(depot.errors[0].0, directory.join("broken.osz"));
+        std::fs::remove_dir_all(directory).ok();
+    }
+}
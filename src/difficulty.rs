@@ -0,0 +1,137 @@
+use crate::{
+    Map,
+    Note,
+};
+
+/// Same scale as osu!mania's star rating.
+const STAR_SCALING: f64 = 0.018;
+/// Length in milliseconds of the sections whose hardest moments are summed.
+const SECTION_LENGTH: usize = 400;
+/// Each section counts this much less than the harder one before it.
+const DECAY_WEIGHT: f64 = 0.9;
+/// How much of a column's strain is left after a second.
+const INDIVIDUAL_DECAY_BASE: f64 = 0.125;
+/// How much of the strain shared by every column is left after a second.
+const OVERALL_DECAY_BASE: f64 = 0.30;
+
+/// A map's rating from how quickly notes follow each other, the way
+/// osu!mania's (pre-2022) star rating works. Each rating is a sum of the
+/// hardest moments, so it doesn't go down for long maps with easy parts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Difficulty {
+    pub stars: f64,
+    /// Rating of each column on its own, `column_count` long.
+    pub columns: Vec<f64>,
+    /// Rating of the strain shared by every column, which comes from how many
+    /// notes there are at once (chords) and how dense the map is overall.
+    pub chord: f64,
+    /// Rating of the strain of the column being pressed, which comes from
+    /// repeated presses in one column (jacks).
+    pub jack: f64,
+}
+
+/// Hardest moments in a [`SECTION_LENGTH`] section.
+#[derive(Clone)]
+struct Peaks {
+    strain: f64,
+    chord: f64,
+    jack: f64,
+    columns: Vec<f64>,
+}
+
+impl Map {
+    pub fn difficulty(&self) -> Difficulty {
+        let mut notes: Vec<(usize, Note)> = self.notes.iter()
+            .enumerate()
+            .flat_map(|(column, notes)| notes.iter().map(move |note| (column, *note)))
+            .collect();
+        notes.sort_by_key(|(column, note)| (note.time, *column));
+
+        let mut hold_end_times = vec![0; self.column_count];
+        let mut individual_strains = vec![0.0; self.column_count];
+        let mut overall_strain = 1.0;
+        let mut strain = 0.0;
+        let mut jack = 0.0;
+        let mut sections = Vec::new();
+        let mut peaks = Peaks {
+            strain: 0.0,
+            chord: 0.0,
+            jack: 0.0,
+            columns: vec![0.0; self.column_count],
+        };
+        let mut section_end = notes.first().map_or(0, |(_, note)| note.time) + SECTION_LENGTH;
+        let mut previous_time = notes.first().map_or(0, |(_, note)| note.time);
+
+        for (column, note) in notes {
+            // Sections without notes keep the strain from before them
+            while note.time > section_end {
+                sections.push(peaks.clone());
+                peaks = Peaks {
+                    strain,
+                    chord: overall_strain,
+                    jack,
+                    columns: individual_strains.clone(),
+                };
+                section_end += SECTION_LENGTH;
+            }
+
+            let end_time = note.end_time.unwrap_or(note.time);
+            let mut hold_factor = 1.0;
+            let mut hold_addition = 0.0;
+            // Notes during a hold in another column are harder, unless the
+            // hold ends with them
+            for hold_end_time in hold_end_times.iter().copied() {
+                if hold_end_time > note.time && hold_end_time < end_time {
+                    hold_addition = 1.0;
+                }
+                if hold_end_time == end_time {
+                    hold_addition = 0.0;
+                }
+                if hold_end_time > end_time {
+                    hold_factor = 1.25;
+                }
+            }
+            hold_end_times[column] = end_time;
+
+            let elapsed = (note.time - previous_time) as f64/1000.0;
+            for individual_strain in individual_strains.iter_mut() {
+                *individual_strain *= INDIVIDUAL_DECAY_BASE.powf(elapsed);
+            }
+            individual_strains[column] += 2.0*hold_factor;
+            overall_strain = overall_strain*OVERALL_DECAY_BASE.powf(elapsed) + (1.0 + hold_addition)*hold_factor;
+            jack = individual_strains[column];
+            strain = jack + overall_strain;
+            previous_time = note.time;
+
+            peaks.strain = peaks.strain.max(strain);
+            peaks.chord = peaks.chord.max(overall_strain);
+            peaks.jack = peaks.jack.max(jack);
+            peaks.columns[column] = peaks.columns[column].max(individual_strains[column]);
+        }
+        sections.push(peaks);
+
+        Difficulty {
+            stars: rating(sections.iter().map(|peaks| peaks.strain)),
+            columns: (0..self.column_count)
+                .map(|column| rating(sections.iter().map(|peaks| peaks.columns[column])))
+                .collect(),
+            chord: rating(sections.iter().map(|peaks| peaks.chord)),
+            jack: rating(sections.iter().map(|peaks| peaks.jack)),
+        }
+    }
+}
+
+/// Sums the hardest sections first, each weighted less than the last.
+fn rating<I>(peaks: I) -> f64 where
+    I: Iterator<Item = f64>
+{
+    let mut peaks: Vec<f64> = peaks.collect();
+    peaks.sort_by(|a, b| b.total_cmp(a));
+    let mut weight = 1.0;
+    let mut total = 0.0;
+    for peak in peaks {
+        total += peak*weight;
+        weight *= DECAY_WEIGHT;
+    }
+    total*STAR_SCALING
+}
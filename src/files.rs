@@ -0,0 +1,107 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{
+        self,
+        Read,
+        Seek,
+    },
+    path::PathBuf,
+    sync::Mutex,
+};
+
+use crate::LoadError;
+
+pub(crate) trait ReadSeek: Read + Seek {}
+
+impl<T> ReadSeek for T where
+    T: Read + Seek
+{}
+
+pub(crate) type Archive = zip::ZipArchive<Box<dyn ReadSeek + Send>>;
+
+pub(crate) fn open_archive<R>(reader: R) -> zip::result::ZipResult<Archive> where
+    R: Read + Seek + Send + 'static
+{
+    zip::ZipArchive::new(Box::new(reader))
+}
+
+/// The non-chart files of a set (audio, backgrounds, videos, ...). Only the
+/// names are kept in memory, contents are read from the archive or directory
+/// when asked for.
+pub struct FileStore {
+    source: FileSource,
+    names: Vec<String>,
+}
+
+enum FileSource {
+    Archive(Mutex<Archive>),
+    Directory(PathBuf),
+}
+
+impl FileStore {
+    pub(crate) fn archive(archive: Archive, names: Vec<String>) -> FileStore {
+        FileStore {
+            source: FileSource::Archive(Mutex::new(archive)),
+            names,
+        }
+    }
+
+    pub(crate) fn directory(path: PathBuf, names: Vec<String>) -> FileStore {
+        FileStore {
+            source: FileSource::Directory(path),
+            names,
+        }
+    }
+
+    /// Names of every file, `/` separated.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.names.iter().map(String::as_str)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.names.iter().any(|file| file == name)
+    }
+
+    /// Reads a whole file.
+    pub fn read(&self, name: &str) -> Result<Vec<u8>, LoadError> {
+        self.with_file(name, |file| {
+            let mut bytes = Vec::new();
+            file.read_to_end(&mut bytes)?;
+            Ok(bytes)
+        })
+    }
+
+    /// Hex MD5 of a file, read a bit at a time instead of all at once.
+    pub(crate) fn checksum(&self, name: &str) -> Result<String, LoadError> {
+        self.with_file(name, |file| {
+            let mut context = md5::Context::new();
+            io::copy(file, &mut context)?;
+            Ok(format!("{:x}", context.compute()))
+        })
+    }
+
+    fn with_file<T, F>(&self, name: &str, f: F) -> Result<T, LoadError> where
+        F: FnOnce(&mut dyn Read) -> io::Result<T>
+    {
+        if !self.contains(name) {
+            return Err(LoadError::FileNotFound(name.to_string()));
+        }
+        match &self.source {
+            FileSource::Archive(archive) => {
+                let mut archive = archive.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                let mut file = archive.by_name(name)?;
+                Ok(f(&mut file)?)
+            },
+            FileSource::Directory(path) => {
+                Ok(f(&mut File::open(path.join(name))?)?)
+            }
+        }
+    }
+}
+
+impl fmt::Debug for FileStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.names.iter()).finish()
+    }
+}
@@ -0,0 +1,61 @@
+use std::collections::HashMap;
+
+use crate::{
+    LoadError,
+    Map,
+    Set,
+};
+
+/// What makes two maps the same map, see [`find_duplicates`].
+#[derive(PartialEq, Eq, Hash)]
+enum Identity<'a> {
+    /// osu! keeps a map's id when it's updated, so it beats the checksum.
+    BeatmapId(u32),
+    /// Files with several charts share a checksum, so the version is needed
+    /// too.
+    Checksum(&'a str, &'a str),
+}
+
+impl Map {
+    fn identity(&self) -> Identity<'_> {
+        match self.metadata.beatmap_id {
+            Some(id) => Identity::BeatmapId(id),
+            None => Identity::Checksum(&self.checksum, &self.metadata.version),
+        }
+    }
+}
+
+impl Set {
+    /// Hex MD5 of `map`'s audio file, see [`Map::audio_checksum`]. Hashed the
+    /// first time it's asked for, and only once for maps sharing the file.
+    pub fn audio_checksum<'a>(&self, map: &'a Map) -> Result<&'a str, LoadError> {
+        if let Some(audio_checksum) = map.audio_checksum.get() {
+            return Ok(audio_checksum);
+        }
+        let shared = self.maps.iter()
+            .filter(|other| other.audio_file_name == map.audio_file_name)
+            .find_map(|other| other.audio_checksum.get());
+        let audio_checksum = match shared {
+            Some(audio_checksum) => audio_checksum.clone(),
+            None => self.files.checksum(&map.audio_file_name)?,
+        };
+        Ok(map.audio_checksum.get_or_init(|| audio_checksum))
+    }
+}
+
+/// Maps that are in `sets` more than once, as `(set, map)` indices. Maps are
+/// the same when they have the same `BeatmapID`, or else the same checksum
+/// and version.
+pub fn find_duplicates(sets: &[Set]) -> Vec<Vec<(usize, usize)>> {
+    let mut groups: HashMap<Identity<'_>, Vec<(usize, usize)>> = HashMap::new();
+    for (set_index, set) in sets.iter().enumerate() {
+        for (map_index, map) in set.maps.iter().enumerate() {
+            groups.entry(map.identity()).or_default().push((set_index, map_index));
+        }
+    }
+    let mut duplicates: Vec<Vec<(usize, usize)>> = groups.into_values()
+        .filter(|group| group.len() > 1)
+        .collect();
+    duplicates.sort();
+    duplicates
+}
@@ -0,0 +1,130 @@
+use std::fmt;
+
+use crate::Map;
+
+/// How far (in milliseconds, either side of the note) a press can be for each
+/// judgement, the way osu!mania derives them from overall difficulty.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HitWindows {
+    /// 300g, also called MAX.
+    pub perfect: f64,
+    pub great: f64,
+    pub good: f64,
+    pub ok: f64,
+    pub meh: f64,
+    /// Presses earlier than this don't count at all.
+    pub miss: f64,
+}
+
+impl HitWindows {
+    pub fn from_od(overall_difficulty: f32) -> HitWindows {
+        let od = f64::from(overall_difficulty.clamp(0.0, 10.0));
+        HitWindows {
+            perfect: 16.0,
+            great: 64.0 - 3.0*od,
+            good: 97.0 - 3.0*od,
+            ok: 127.0 - 3.0*od,
+            meh: 151.0 - 3.0*od,
+            miss: 188.0 - 3.0*od,
+        }
+    }
+
+    /// Judges a press `offset` milliseconds before the note (negative when
+    /// late). `None` when the press is too early or too late to count.
+    pub fn judge(&self, offset: f64) -> Option<Judgement> {
+        let offset = offset.abs();
+        if offset <= self.perfect {
+            Some(Judgement::Perfect)
+        } else if offset <= self.great {
+            Some(Judgement::Great)
+        } else if offset <= self.good {
+            Some(Judgement::Good)
+        } else if offset <= self.ok {
+            Some(Judgement::Ok)
+        } else if offset <= self.meh {
+            Some(Judgement::Meh)
+        } else if offset <= self.miss {
+            Some(Judgement::Miss)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Judgement {
+    Perfect,
+    Great,
+    Good,
+    Ok,
+    Meh,
+    Miss,
+}
+
+impl fmt::Display for Judgement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Judgement::Perfect => "PERFECT",
+            Judgement::Great => "GREAT",
+            Judgement::Good => "GOOD",
+            Judgement::Ok => "OK",
+            Judgement::Meh => "MEH",
+            Judgement::Miss => "MISS",
+        };
+        f.write_str(name)
+    }
+}
+
+impl Map {
+    pub fn hit_windows(&self) -> HitWindows {
+        HitWindows::from_od(self.overall_difficulty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn windows_shrink_with_overall_difficulty() {
+        for (od, windows) in [
+            (0.0, [16.0, 64.0, 97.0, 127.0, 151.0, 188.0]),
+            (5.0, [16.0, 49.0, 82.0, 112.0, 136.0, 173.0]),
+            (10.0, [16.0, 34.0, 67.0, 97.0, 121.0, 158.0]),
+            // Clamped to 0-10
+            (-1.0, [16.0, 64.0, 97.0, 127.0, 151.0, 188.0]),
+            (11.0, [16.0, 34.0, 67.0, 97.0, 121.0, 158.0]),
+        ] {
+            let [perfect, great, good, ok, meh, miss] = windows;
+            assert_eq!(HitWindows::from_od(od), HitWindows {
+                perfect,
+                great,
+                good,
+                ok,
+                meh,
+                miss,
+            }, "OD {}", od);
+        }
+    }
+
+    #[test]
+    fn judges_on_either_side_of_the_note() {
+        let windows = HitWindows::from_od(5.0);
+        for (offset, judgement) in [
+            (0.0, Some(Judgement::Perfect)),
+            (16.0, Some(Judgement::Perfect)),
+            (16.5, Some(Judgement::Great)),
+            (49.0, Some(Judgement::Great)),
+            (-49.0, Some(Judgement::Great)),
+            (82.0, Some(Judgement::Good)),
+            (112.0, Some(Judgement::Ok)),
+            (136.0, Some(Judgement::Meh)),
+            (-136.5, Some(Judgement::Miss)),
+            (173.0, Some(Judgement::Miss)),
+            (173.5, None),
+            (-173.5, None),
+        ] {
+            assert_eq!(windows.judge(offset), judgement, "offset {}", offset);
+        }
+    }
+}
@@ -1,331 +1,611 @@
 use std::{
-    collections::HashMap,
+    collections::HashSet,
+    fmt,
+    fs::File,
     io::{
-        BufRead,
-        BufReader,
         Read,
-        Seek
+        Seek,
+        Write,
     },
+    path::{
+        Path,
+        PathBuf,
+    },
+    sync::OnceLock,
 };
 
-// Assumed to be 4k osu!mania
-#[derive(Debug)]
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+mod bms;
+mod cache;
+mod convert;
+mod depot;
+mod difficulty;
+mod files;
+mod fingerprint;
+mod judgement;
+mod malody;
+mod mods;
+mod osu;
+mod quaver;
+mod report;
+mod robot;
+mod search;
+mod stats;
+mod stepmania;
+mod timing;
+
+pub use report::{
+    Diagnostic,
+    FileReport,
+    ParseReport,
+    Warning,
+};
+pub use cache::DepotCache;
+pub use convert::ColumnStrategy;
+pub use depot::{
+    Depot,
+    ARCHIVE_EXTENSIONS,
+};
+pub use difficulty::Difficulty;
+pub use files::FileStore;
+pub use fingerprint::find_duplicates;
+pub use judgement::{
+    HitWindows,
+    Judgement,
+};
+pub use mods::{
+    MAX_RATE,
+    MIN_RATE,
+};
+pub use robot::{
+    RemovedNote,
+    RobotLimit,
+    RobotLimits,
+};
+pub use search::{
+    search,
+    Query,
+};
+pub use stats::Statistics;
+pub use timing::TimingPoint;
+
+/// Largest key count a map can have.
+pub const MAX_COLUMN_COUNT: usize = 10;
+
+/// Used for difficulty settings a chart leaves out.
+pub(crate) const DEFAULT_DIFFICULTY: f32 = 5.0;
+
+// Assumed to be osu!mania
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Map {
+    /// The `vN` from the .osu file's header, 0 for charts imported from
+    /// other games.
+    pub format_version: u32,
+    /// Empty for charts that are made up of samples only (BMS).
     pub audio_file_name: String,
     pub audio_lead_in: usize,
     pub full_title: String,
-    pub notes: [Vec<usize>; 4],
+    pub metadata: Metadata,
+    pub column_count: usize,
+    pub hp_drain_rate: f32,
+    /// Decides the hit windows, see [`Map::hit_windows`].
+    pub overall_difficulty: f32,
+    /// Notes in each column, `column_count` long.
+    pub notes: Vec<Vec<Note>>,
+    /// Sorted by time.
+    pub timing_points: Vec<TimingPoint>,
+    /// Sounds played alongside the audio file, sorted by time.
+    pub samples: Vec<Sample>,
+    /// Hex MD5 of the chart file the map was read from, which for .osu files
+    /// is the checksum osu! identifies maps by. Maps from the same file (like
+    /// a simfile's charts) share it, and copies made by conversions and mods
+    /// keep it.
+    pub checksum: String,
+    /// Hex MD5 of the audio file, for telling whether maps are timed to the
+    /// same audio. Hashing means reading the whole file, so it's only filled
+    /// in once [`Set::audio_checksum`] is asked for it.
+    #[serde(skip)]
+    pub audio_checksum: OnceLock<String>,
+}
+
+/// Everything in the [Metadata] section. Titles and artists fall back to each
+/// other when only the ASCII or only the Unicode spelling is present.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Metadata {
+    pub title: String,
+    pub title_unicode: String,
+    pub artist: String,
+    pub artist_unicode: String,
+    pub creator: String,
+    /// Difficulty name.
+    pub version: String,
+    pub source: String,
+    pub tags: Vec<String>,
+    /// `None` for unsubmitted maps.
+    pub beatmap_id: Option<u32>,
+    pub beatmap_set_id: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Note {
+    pub time: usize,
+    /// When the note must be released. `None` for regular (non-hold) notes.
+    pub end_time: Option<usize>,
+}
+
+impl Note {
+    pub fn is_hold(&self) -> bool {
+        self.end_time.is_some()
+    }
+}
+
+/// A sound that plays on its own at a set time, like a BMS keysound or an
+/// osu! storyboard sample.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Sample {
+    pub time: usize,
+    /// Name of the sound in the set's files.
+    pub file_name: String,
 }
 
 #[derive(Debug)]
 pub struct Set {
     pub maps: Vec<Map>,
-    pub files: HashMap<String, Vec<u8>>
+    /// Every file in the set that isn't a chart.
+    pub files: FileStore,
+    /// Warnings and rejections for every chart file in the set.
+    pub report: ParseReport,
+}
+
+/// Sections of a .osu file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Section {
+    General,
+    Editor,
+    Metadata,
+    Difficulty,
+    Events,
+    TimingPoints,
+    Colours,
+    HitObjects,
+}
+
+impl fmt::Display for Section {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Section::General => "General",
+            Section::Editor => "Editor",
+            Section::Metadata => "Metadata",
+            Section::Difficulty => "Difficulty",
+            Section::Events => "Events",
+            Section::TimingPoints => "TimingPoints",
+            Section::Colours => "Colours",
+            Section::HitObjects => "HitObjects",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Reasons a single .osu file is rejected.
+#[derive(Debug)]
+pub enum ParseError {
+    Io(std::io::Error),
+    InvalidHeader,
+    UnsupportedFormatVersion(String),
+    UnknownTag(String),
+    MalformedLine {
+        section: Section,
+        line: String,
+    },
+    InvalidNumber {
+        field: &'static str,
+        value: String,
+    },
+    UnsupportedMode(String),
+    UnsupportedSpecialStyle(String),
+    UnsupportedColumnCount(String),
+    MissingAudioFile,
+    NotMania,
+    MissingTitle,
+    MissingVersion,
+    MissingColumnCount,
+    /// None of the file's charts can be loaded.
+    NoSupportedCharts,
+    /// The chart isn't valid YAML/JSON or doesn't have the expected fields.
+    InvalidDocument(String),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Io(error) => write!(f, "failed to read map: {}", error),
+            ParseError::InvalidHeader => f.write_str("map does not start with an osu file format header"),
+            ParseError::UnsupportedFormatVersion(version) => write!(f, "osu file format v{} is not supported", version),
+            ParseError::UnknownTag(line) => write!(f, "unknown tag: {}", line),
+            ParseError::MalformedLine { section, line } => write!(f, "lines in {} section must be key value pairs: {}", section, line),
+            ParseError::InvalidNumber { field, value } => write!(f, "{} is not a valid number: {}", field, value),
+            ParseError::UnsupportedMode(mode) => write!(f, "only osu!mania maps are supported (mode {})", mode),
+            ParseError::UnsupportedSpecialStyle(style) => write!(f, "only non-special style is supported (special style {})", style),
+            ParseError::UnsupportedColumnCount(count) => write!(f, "only 1k to {}k is supported (circle size {})", MAX_COLUMN_COUNT, count),
+            ParseError::MissingAudioFile => f.write_str("map does not have an audio file listed"),
+            ParseError::NotMania => f.write_str("map does not report to be osu!mania"),
+            ParseError::MissingTitle => f.write_str("map does not have a title"),
+            ParseError::MissingVersion => f.write_str("map does not report a version"),
+            ParseError::MissingColumnCount => f.write_str("map does not report its circle size (also column count)"),
+            ParseError::NoSupportedCharts => f.write_str("file does not have any supported charts"),
+            ParseError::InvalidDocument(error) => write!(f, "chart is not valid: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ParseError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for ParseError {
+    fn from(error: std::io::Error) -> Self {
+        ParseError::Io(error)
+    }
+}
+
+/// Reasons a whole set can't be loaded.
+#[derive(Debug)]
+pub enum LoadError {
+    Io(std::io::Error),
+    Zip(zip::result::ZipError),
+    FileNotFound(String),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io(error) => write!(f, "failed to read set: {}", error),
+            LoadError::Zip(error) => write!(f, "failed to read archive: {}", error),
+            LoadError::FileNotFound(name) => write!(f, "set does not contain {}", name),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LoadError::Io(error) => Some(error),
+            LoadError::Zip(error) => Some(error),
+            LoadError::FileNotFound(_) => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for LoadError {
+    fn from(error: std::io::Error) -> Self {
+        LoadError::Io(error)
+    }
+}
+
+impl From<zip::result::ZipError> for LoadError {
+    fn from(error: zip::result::ZipError) -> Self {
+        LoadError::Zip(error)
+    }
+}
+
+impl Map {
+    /// Parses a single .osu file. Use [`Set::from_osz`] to get warnings too.
+    pub fn from_osu<R>(mut reader: R) -> Result<Map, ParseError> where
+        R: Read
+    {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        match FileReport::parse("", &bytes[..], osu::read) {
+            (Some(mut map), _) => {
+                map.checksum = checksum(&bytes);
+                Ok(map)
+            },
+            (None, report) => Err(report.rejection.expect("rejected map has a reason").reason),
+        }
+    }
+
+    /// Writes the map as an osu file format v14 .osu file that
+    /// [`Map::from_osu`] reads back into the same map.
+    pub fn write_osu<W>(&self, writer: W) -> std::io::Result<()> where
+        W: Write
+    {
+        osu::write(self, writer)
+    }
+
+    /// The name osu! gives this map's .osu file:
+    /// `Artist - Title (Creator) [Version].osu`.
+    pub fn osu_file_name(&self) -> String {
+        let name = format!(
+            "{} - {} ({}) [{}].osu",
+            self.metadata.artist,
+            self.metadata.title,
+            self.metadata.creator,
+            self.metadata.version,
+        );
+        // Characters Windows doesn't allow in file names
+        name.chars()
+            .filter(|character| !matches!(character, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|'))
+            .collect()
+    }
 }
 
 impl Set {
-    pub fn from_osz<R>(reader: R) -> Set where
-        R: Read+Seek
+    /// Loads every difficulty in an .osz archive. Difficulties that fail to
+    /// parse are recorded in `report` rather than failing the whole set. The
+    /// archive is kept open for reading the set's other files later.
+    ///
+    /// Other zipped chart packs, like Quaver's .qp and Malody's .mcz, load
+    /// the same way.
+    pub fn from_osz<R>(reader: R) -> Result<Set, LoadError> where
+        R: Read+Seek+Send+'static
     {
-        let mut archive = zip::ZipArchive::new(reader).unwrap();
+        let mut archive = files::open_archive(reader)?;
         let mut maps = Vec::new();
-        let mut files = HashMap::new();
+        let mut report = ParseReport::default();
+        let mut names = Vec::new();
+        let all_names: Vec<String> = archive.file_names().map(str::to_string).collect();
         for i in 0..archive.len() {
-            let mut file = archive.by_index(i).unwrap();
-            let extention = std::path::Path::new(file.name()).extension();
-            match extention {
-                Some(extention) if extention.to_ascii_lowercase().to_str().unwrap() == "osu" => {
-                    if true {
-                            eprintln!("Parsing {:#?} as a osu map", file.name());
-
-                            struct HitCircle {
-                                x: usize,
-                                time: usize
-                            }
-
-                            let mut valid = true;
-                            let mut audio_file_name: Option<String> = None;
-                            let mut audio_lead_in: usize = 0;
-                            let mut mode: Option<usize> = None;
-                            let mut title: Option<String> = None;
-                            let mut version: Option<String> = None;
-                            let mut column_count: Option<usize> = None;
-                            let mut hit_circles: Vec<HitCircle> = Vec::new();
-                            enum ParseState {
-                                None,
-                                General,
-                                Editor,
-                                Metadata,
-                                Difficulty,
-                                Events,
-                                TimingPoints,
-                                Colours,
-                                HitObjects,
-                            }
-                            let mut parse_state = ParseState::None;
-                            let mut lines = BufReader::new(file).lines();
-                            if matches!(lines.next(), Some(line) if matches!(&line, Ok(line) if line.trim() == "osu file format v14")) {
-                                for line in lines {
-                                    let line = line.unwrap();
-                                    let line= line.trim();
-                                    match line {
-                                        "" => {},
-                                        _ if line.starts_with("//") => {},
-                                        "[General]" => parse_state = ParseState::General,
-                                        "[Editor]" => parse_state = ParseState::Editor,
-                                        "[Metadata]" => parse_state = ParseState::Metadata,
-                                        "[Difficulty]" => parse_state = ParseState::Difficulty,
-                                        "[Events]" => parse_state = ParseState::Events,
-                                        "[TimingPoints]" => parse_state = ParseState::TimingPoints,
-                                        "[Colours]" => parse_state = ParseState::Colours,
-                                        "[HitObjects]" => parse_state = ParseState::HitObjects,
-                                        _ => match parse_state {
-                                            ParseState::None => {
-                                                println!("Unknown tag: {}", line);
-                                                valid = false;
-                                                break;
-                                            },
-                                            ParseState::General => {
-                                                let parts: Vec<&str> = line.split(": ").collect();
-                                                if parts.len() != 2 {
-                                                    eprintln!("Lines in general section must constist of exactly 2 pairs.");
-                                                    valid = false;
-                                                    break;
-                                                }
-                                                let key = *parts.get(0).unwrap();
-                                                let value = *parts.get(1).unwrap();
-                                                
-                                                match key {
-                                                    "AudioFilename" => audio_file_name = Some(value.to_string()),
-                                                    "AudioLeadIn" => audio_lead_in = value.parse().unwrap(),
-                                                    "Mode" => {
-                                                        if value != "3" {
-                                                            eprintln!("Only osu!mania maps are supported.");
-                                                            valid = false;
-                                                            break;
-                                                        } else {
-                                                            mode = Some(3);
-                                                        }
-                                                    },
-                                                    "SpecialStyle" => {
-                                                        if value != "0" {
-                                                            eprintln!("Only non-special style is supported.");
-                                                            valid = false;
-                                                            break;
-                                                        }
-                                                    },
-                                                    // Ignored keys
-                                                    "AudioHash" |
-                                                    "PreviewTime" |
-                                                    "Countdown" |
-                                                    "SampleSet" |
-                                                    "StackLeniency" |
-                                                    "LetterboxInBreaks" |
-                                                    "StoryFireInFront" |
-                                                    "UseSkipSprites" |
-                                                    "AlwaysShowPlayfield" |
-                                                    "OverlayPosition" |
-                                                    "SkinPreference" |
-                                                    "EpilepsyWarning" |
-                                                    "CountdownOffset" |
-                                                    "WidescreenStoryboard" |
-                                                    "SampleMatchPlaybackRate" => {},
-                                                    _ => eprintln!("Unrecognized key in General section: {}", key)
-                                                }
-                                            },
-                                            ParseState::Editor => {
-                                                let parts = line.split_once(": ");
-                                                match parts {
-                                                    Some((key, _value)) => {
-                                                        match key {
-                                                            // Ignored keys
-                                                            "Bookmarks" |
-                                                            "DistanceSpacing" |
-                                                            "BeatDivisor" |
-                                                            "GridSize" |
-                                                            "TimelineZoom" => {},
-                                                            _ => eprintln!("Unrecognized key in Editor section: {}", key)
-                                                        }
-                                                    },
-                                                    None => {
-                                                        eprintln!("Lines in Editor section must constist of exactly 2 pairs.");
-                                                        valid = false;
-                                                        break;
-                                                    }
-                                                }
-                                            },
-                                            ParseState::Metadata => {
-                                                let parts = line.split_once(":");
-                                                match parts {
-                                                    Some((key, value)) => {
-                                                        match key {
-                                                            "TitleUnicode" => title = Some(value.to_string()),
-                                                            "Version" => version = Some(value.to_owned()),
-                                                            // Ignored keys
-                                                            "Title" |
-                                                            "Artist" |
-                                                            "ArtistUnicode" |
-                                                            "Creator" |
-                                                            "Source" |
-                                                            "Tags" |
-                                                            "BeatmapID" |
-                                                            "BeatmapSetID" => {},
-                                                            _ => eprintln!("Unrecognized key in Metadata section: {}", key)
-                                                        }
-                                                    },
-                                                    None => {
-                                                        eprintln!("Lines in Metadata section must constist of exactly 2 pairs. {}", line);
-                                                        valid = false;
-                                                        break;
-                                                    }
-                                                }
-                                            },
-                                            ParseState::Difficulty => {
-                                                let parts: Vec<&str> = line.split(":").collect();
-                                                if parts.len() != 2 {
-                                                    eprintln!("Lines in difficulty section must constist of exactly 2 pairs.");
-                                                    valid = false;
-                                                    break;
-                                                }
-                                                let key = *parts.get(0).unwrap();
-                                                let value = *parts.get(1).unwrap();
-                                                
-                                                match key {
-                                                    "CircleSize" => if value == "4" {
-                                                        column_count = Some(4);
-                                                    } else {
-                                                        eprintln!("Only 4k is supported.");
-                                                        valid = false;
-                                                        break;
-                                                    }
-                                                    // Ignored keys
-                                                    "HPDrainRate" |
-                                                    "OverallDifficulty" |
-                                                    "ApproachRate" |
-                                                    "SliderMultiplier" |
-                                                    "SliderTickRate" => {},
-                                                    _ => eprintln!("Unrecognized key in Difficulty section: {}", key)
-                                                }
-                                            },
-                                            ParseState::Events => {},
-                                            ParseState::TimingPoints => {},
-                                            ParseState::Colours => {},
-                                            ParseState::HitObjects => {
-                                                // Assumes osu!mania
-                                                enum HitObjectParseState {
-                                                    X,
-                                                    Y,
-                                                    Time,
-                                                    Type,
-                                                }
-                                                let mut state = HitObjectParseState::X;
-                                                let mut object_valid = true;
-                                                let mut x: Option<usize> = None;
-                                                let mut time: Option<usize> = None;
-                                                for part in line.split(",") {
-                                                    match state {
-                                                        HitObjectParseState::X => {
-                                                            x = Some(part.parse::<usize>().unwrap());
-                                                            state = HitObjectParseState::Y;
-                                                        },
-
-                                                        HitObjectParseState::Y => state = HitObjectParseState::Time,
-                                                        HitObjectParseState::Time => {
-                                                            time = Some(part.parse::<usize>().unwrap());
-                                                            state = HitObjectParseState::Type;
-                                                        },
-                                                        HitObjectParseState::Type => if part != "1" {
-                                                            eprintln!("Only hit objects of type hit circle (1) are supported.");
-                                                            object_valid = false;
-                                                            break;
-                                                        } else {
-                                                            break; // Rest aren't read for now
-                                                        }
-                                                    }
-                                                }
-
-                                                if object_valid {
-                                                    if let Some(x) = x {
-                                                        if let Some(time) = time {
-                                                            hit_circles.push(HitCircle {
-                                                                x,
-                                                                time,
-                                                            });
-                                                        } else {
-                                                            eprintln!("Hit object does not have a time value. Ignoring...");
-                                                        }
-                                                    } else {
-                                                        eprintln!("Hit object does not have an x value. Ignoring...");
-                                                    }
-                                                } else {
-                                                    eprintln!("Hit object is invalid. Ignoring...");
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                            } else {
-                                valid = false;
-                            }
-
-                            if valid {
-                                if let Some(audio_file_name) = audio_file_name {
-                                    if mode == Some(3) {
-                                        if let Some(title) = title {
-                                            if let Some(version) = version {
-                                                if let Some(column_count) = column_count {
-                                                    let mut notes = [Vec::new(), Vec::new(), Vec::new(), Vec::new()];
-
-                                                    for hit_circle in hit_circles {
-                                                        let column = hit_circle.x*column_count/512;
-                                                        if column < column_count {
-                                                            notes[column].push(hit_circle.time);
-                                                        } else {
-                                                            eprintln!("Hit Circle does not fit in columns. Ignoring...");
-                                                        }
-                                                    }
-
-                                                    maps.push(Map {
-                                                        audio_file_name,
-                                                        audio_lead_in,
-                                                        full_title: format!("{} - {}", version, title),
-                                                        notes
-                                                    });
-                                                } else {
-                                                    eprintln!("Map does not report its circle size (also column count). Ignoring...");
-                                                }
-                                            } else {
-                                                eprintln!("Map does not report a version. Ignoring...");
-                                            }
-                                        } else {
-                                            eprintln!("Map does not have a title. Ignoring...");
-                                        }
-                                    } else {
-                                        eprintln!("Map does not report to be osu!mania. Ignoring...");
-                                    }
-                                } else {
-                                    eprintln!("Map does not have an audio file listed. Ignoring...");
-                                }
-                            } else {
-                                eprintln!("Map is invalid. Ignoring...");
-                            }
-                        }
-                },
-                _ => {
-                    {
-                        let name = file.name().to_string();
-                        eprintln!("Reading file {:#?}...", name);
-                        let mut bytes = Vec::new();
-                        file.read_to_end(&mut bytes).expect(format!("failed to read file: {}", name).as_str());
-                        files.insert(name, bytes);
-                    }
+            let file = archive.by_index(i)?;
+            let name = file.name().to_string();
+            if is_superseded(&name, &all_names) {
+                continue;
+            }
+            if !parse_chart(&name, file, &mut maps, &mut report) {
+                names.push(name);
+            }
+        }
+        resolve_samples(&mut maps, &names);
+        Ok(Set {
+            maps,
+            files: FileStore::archive(archive, names),
+            report,
+        })
+    }
+
+    /// Loads an extracted set, like the song folders in an osu! install.
+    /// Parsing is the same as [`Set::from_osz`], with files named by their
+    /// `/` separated path relative to `path`.
+    pub fn from_dir<P>(path: P) -> Result<Set, LoadError> where
+        P: AsRef<Path>
+    {
+        let mut all_names = Vec::new();
+        let mut directories = vec![PathBuf::new()];
+        while let Some(directory) = directories.pop() {
+            let mut entries = std::fs::read_dir(path.as_ref().join(&directory))?
+                .collect::<Result<Vec<_>, _>>()?;
+            entries.sort_by_key(|entry| entry.file_name());
+            for entry in entries {
+                let relative_path = directory.join(entry.file_name());
+                if entry.file_type()?.is_dir() {
+                    directories.push(relative_path);
+                } else {
+                    all_names.push(relative_path.components()
+                        .map(|component| component.as_os_str().to_string_lossy())
+                        .collect::<Vec<_>>()
+                        .join("/"));
                 }
             }
         }
 
-        Set {
+        let mut maps = Vec::new();
+        let mut report = ParseReport::default();
+        let mut names = Vec::new();
+        for name in all_names.iter() {
+            if is_superseded(name, &all_names) {
+                continue;
+            }
+            if is_chart(name) {
+                parse_chart(name, File::open(path.as_ref().join(name))?, &mut maps, &mut report);
+            } else {
+                names.push(name.clone());
+            }
+        }
+        resolve_samples(&mut maps, &names);
+        Ok(Set {
             maps,
-            files,
+            files: FileStore::directory(path.as_ref().to_path_buf(), names),
+            report,
+        })
+    }
+
+    /// Writes the set as an .osz archive: a .osu file per map and every file
+    /// in `files`, so it can be imported back into osu!.
+    pub fn write_osz<W>(&self, writer: W) -> Result<(), LoadError> where
+        W: Write+Seek
+    {
+        let mut archive = zip::ZipWriter::new(writer);
+        let options = zip::write::FileOptions::default();
+        let mut written = HashSet::new();
+        for map in self.maps.iter() {
+            // Difficulties can end up with the same name, e.g. after conversion
+            let mut name = map.osu_file_name();
+            let mut duplicate = 1;
+            while written.contains(&name) || self.files.contains(&name) {
+                duplicate += 1;
+                name = format!("{} ({}).osu", map.osu_file_name().trim_end_matches(".osu"), duplicate);
+            }
+            archive.start_file(name.as_str(), options)?;
+            map.write_osu(&mut archive)?;
+            written.insert(name);
         }
+        for name in self.files.names() {
+            archive.start_file(name, options)?;
+            archive.write_all(&self.files.read(name)?)?;
+        }
+        archive.finish()?;
+        Ok(())
+    }
+}
+
+/// Path of a file that `file_name` refers to, which is relative to the
+/// directory `file_name` is in.
+pub(crate) fn sibling_file_name(file_name: &str, name: &str) -> String {
+    match file_name.rsplit_once('/') {
+        Some((directory, _)) => format!("{}/{}", directory, name),
+        None => name.to_string(),
     }
-}
\ No newline at end of file
+}
+
+/// Sample extensions tried when a chart names a file the set doesn't have.
+const SAMPLE_EXTENSIONS: [&str; 4] = ["ogg", "wav", "mp3", "flac"];
+
+/// BMS packs often have their keysounds converted (usually .wav to .ogg)
+/// without the charts being updated. Points samples at the file with the same
+/// stem when the one named is missing.
+fn resolve_samples(maps: &mut [Map], names: &[String]) {
+    for sample in maps.iter_mut().flat_map(|map| map.samples.iter_mut()) {
+        if names.contains(&sample.file_name) {
+            continue;
+        }
+        let path = Path::new(&sample.file_name);
+        let found = SAMPLE_EXTENSIONS.iter()
+            .map(|extension| path.with_extension(extension).to_string_lossy().into_owned())
+            .find(|name| names.contains(name));
+        if let Some(name) = found {
+            sample.file_name = name;
+        }
+    }
+}
+
+/// Chart formats a set can contain, by file extension.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ChartFormat {
+    Osu,
+    StepMania,
+    StepManiaSsc,
+    Quaver,
+    Bms,
+    Malody,
+}
+
+fn chart_format(name: &str) -> Option<ChartFormat> {
+    let extention = Path::new(name).extension()?.to_str()?.to_ascii_lowercase();
+    match extention.as_str() {
+        "osu" => Some(ChartFormat::Osu),
+        "sm" => Some(ChartFormat::StepMania),
+        "ssc" => Some(ChartFormat::StepManiaSsc),
+        "qua" => Some(ChartFormat::Quaver),
+        "bms" | "bme" | "bml" => Some(ChartFormat::Bms),
+        "mc" => Some(ChartFormat::Malody),
+        _ => None,
+    }
+}
+
+fn is_chart(name: &str) -> bool {
+    chart_format(name).is_some()
+}
+
+/// StepMania writes a .sm next to each .ssc for older versions. Only the
+/// .ssc is read when both are there.
+fn is_superseded(name: &str, names: &[String]) -> bool {
+    chart_format(name) == Some(ChartFormat::StepMania) && names.iter().any(|other| {
+        chart_format(other) == Some(ChartFormat::StepManiaSsc)
+            && Path::new(other).with_extension("") == Path::new(name).with_extension("")
+    })
+}
+
+/// Parses `reader` into `maps` if `name` is a chart. Returns whether it was.
+fn parse_chart<R>(name: &str, mut reader: R, maps: &mut Vec<Map>, report: &mut ParseReport) -> bool where
+    R: Read
+{
+    if !is_chart(name) {
+        return false;
+    }
+    // Read up front for the checksum
+    let mut bytes = Vec::new();
+    if let Err(error) = reader.read_to_end(&mut bytes) {
+        report.files.push(FileReport {
+            file_name: name.to_string(),
+            warnings: Vec::new(),
+            rejection: Some(Diagnostic::at(None, error.into())),
+        });
+        return true;
+    }
+    let reader = &bytes[..];
+    let first_map = maps.len();
+    let file_report = match chart_format(name) {
+        Some(ChartFormat::Osu) => {
+            let (map, file_report) = FileReport::parse(name, reader, osu::read);
+            maps.extend(map);
+            file_report
+        },
+        Some(ChartFormat::StepMania | ChartFormat::StepManiaSsc) => {
+            let (charts, file_report) = FileReport::parse(name, reader, stepmania::read);
+            maps.extend(charts.into_iter().flatten());
+            file_report
+        },
+        Some(ChartFormat::Quaver) => {
+            let (map, file_report) = FileReport::parse(name, reader, quaver::read);
+            maps.extend(map);
+            file_report
+        },
+        Some(ChartFormat::Bms) => {
+            let (map, file_report) = FileReport::parse(name, reader, bms::read);
+            maps.extend(map);
+            file_report
+        },
+        Some(ChartFormat::Malody) => {
+            let (map, file_report) = FileReport::parse(name, reader, malody::read);
+            maps.extend(map);
+            file_report
+        },
+        None => return false,
+    };
+    let checksum = checksum(&bytes);
+    for map in maps[first_map..].iter_mut() {
+        map.checksum = checksum.clone();
+    }
+    report.files.push(file_report);
+    true
+}
+
+/// A map's [`Map::full_title`]. Conversions and mods rebuild it with their
+/// new version so that it reads back the same from a .osu file.
+pub(crate) fn full_title(version: &str, title_unicode: &str) -> String {
+    format!("{} - {}", version, title_unicode)
+}
+
+/// Reads a number from a chart file, naming `field` if it isn't one.
+pub(crate) fn parse_number<T>(field: &'static str, value: &str) -> Result<T, ParseError> where
+    T: std::str::FromStr
+{
+    value.trim().parse().map_err(|_| ParseError::InvalidNumber {
+        field,
+        value: value.to_string(),
+    })
+}
+
+/// Like [`parse_number`], for formats where infinity and NaN are never
+/// meant.
+pub(crate) fn parse_finite(field: &'static str, value: &str) -> Result<f64, ParseError> {
+    match parse_number::<f64>(field, value) {
+        Ok(number) if number.is_finite() => Ok(number),
+        _ => Err(ParseError::InvalidNumber {
+            field,
+            value: value.to_string(),
+        }),
+    }
+}
+
+/// `None` for fields left empty.
+pub(crate) fn non_empty(value: &str) -> Option<String> {
+    if value.is_empty() {
+        None
+    } else {
+        Some(value.to_string())
+    }
+}
+
+/// Hex MD5 of `bytes`.
+pub(crate) fn checksum(bytes: &[u8]) -> String {
+    format!("{:x}", md5::compute(bytes))
+}
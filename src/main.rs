@@ -1,4 +1,4 @@
-use std::{io::{
+use std::{collections::HashMap, io::{
     Cursor,
     Write,
 }, time::{
@@ -6,23 +6,45 @@ use std::{io::{
     Instant,
 }};
 
+use micro_vsrg::{
+    ColumnStrategy,
+    Depot,
+    DepotCache,
+    Judgement,
+    Query,
+    RobotLimits,
+};
 use rodio::Source;
 use rppal::gpio::{Gpio, Level};
 
-const VERSION: &'static str = env!("CARGO_PKG_VERSION");
+use stretch::TimeStretch;
 
-const P1LED1PIN: u8 = 2;
-const P1LED2PIN: u8 = 3;
-const P1LED3PIN: u8 = 27;
-const P1LED4PIN: u8 = 10;
+mod stretch;
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// Parsed sets from the last run, so only new and changed sets are parsed
+const DEPOT_CACHE_PATH: &str = "./map_depot.cache";
+
+// One entry per lane, left to right. Add pins here for larger cabinets.
+const P1LEDPINS: [u8; 4] = [2, 3, 27, 10];
+
+// Columns in song select's density graph
+const DENSITY_WIDTH: usize = 16;
+const DENSITY_BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
 
 const BUTTON_DEBOUNCING: Duration = Duration::from_millis(50);
-const P1B1PIN: u8 = 4;
-const P1B2PIN: u8 = 17;
-const P1B3PIN: u8 = 22;
-const P1B4PIN: u8 = 9;
+const P1BPINS: [u8; 4] = [4, 17, 22, 9];
+
+// How long before a note its led lights up, so robots have time to react
+const LED_LEAD: f64 = 2000.0;
 
-const HIT_RANGE: isize = 2000;
+// What the cabinet's robots can keep up with
+const ROBOT_LIMITS: RobotLimits = RobotLimits {
+    column_interval: 150,
+    max_simultaneous: 2,
+    lane_switch: 100,
+};
 
 fn main() {
     let stdin = std::io::stdin();
@@ -31,29 +53,62 @@ fn main() {
     println!("Welcome to Micro VSRG {}", VERSION);
 
     let gpio = Gpio::new().unwrap();
-    let mut p1led1 = gpio.get(P1LED1PIN).unwrap().into_output_low();
-    let mut p1led2 = gpio.get(P1LED2PIN).unwrap().into_output_low();
-    let mut p1led3 = gpio.get(P1LED3PIN).unwrap().into_output_low();
-    let mut p1led4 = gpio.get(P1LED4PIN).unwrap().into_output_low();
-    let p1b1 = gpio.get(P1B1PIN).unwrap().into_input_pulldown();
-    let p1b2 = gpio.get(P1B2PIN).unwrap().into_input_pulldown();
-    let p1b3 = gpio.get(P1B3PIN).unwrap().into_input_pulldown();
-    let p1b4 = gpio.get(P1B4PIN).unwrap().into_input_pulldown();
+    let mut p1leds: Vec<_> = P1LEDPINS.iter().map(|pin| gpio.get(*pin).unwrap().into_output_low()).collect();
+    let p1bs: Vec<_> = P1BPINS.iter().map(|pin| gpio.get(*pin).unwrap().into_input_pulldown()).collect();
 
     println!("Loading maps from ./map_depot ...");
-    let depot = std::fs::read_dir("./map_depot").unwrap();
-    let mut sets = Vec::new();
-    for entry in depot {
-        let entry = std::fs::File::open(entry.unwrap().path()).unwrap();
-        sets.push(micro_vsrg::Set::from_osz(entry));
+    let cache = DepotCache::open(DEPOT_CACHE_PATH);
+    let depot = Depot::load_cached("./map_depot", &cache).unwrap();
+    for (set, path) in depot.sets.iter().zip(depot.paths.iter()) {
+        for file in set.report.rejected() {
+            if let Some(rejection) = &file.rejection {
+                eprintln!("Skipping {:#?} in {:#?}: {}", file.file_name, path, rejection);
+            }
+        }
+    }
+    for (path, error) in depot.errors.iter() {
+        eprintln!("Skipping {:#?}: {}", path, error);
+    }
+    let sets = depot.sets;
+    if let Err(error) = cache.save() {
+        eprintln!("Failed to save {}: {}", DEPOT_CACHE_PATH, error);
     }
     println!("{} set(s) loaded!", sets.len());
+    for duplicates in micro_vsrg::find_duplicates(&sets) {
+        let (set_id, map_id) = duplicates[0];
+        let copies: Vec<String> = duplicates.iter().map(|(set_id, map_id)| format!("{},{}", set_id, map_id)).collect();
+        println!("{:#?} is loaded more than once: {}", sets[set_id].maps[map_id].full_title, copies.join(" "));
+    }
+
+    let query = loop {
+        print!("Search titles, artists, mappers, tags and difficulties, with filters like keys:7 stars:2-4 bpm:150- length:-120 (blank for all): ");
+        stdout.flush().unwrap();
+        let mut input = String::new();
+        stdin.read_line(&mut input).unwrap();
+        match input.parse::<Query>() {
+            Ok(query) => break query,
+            Err(error) => println!("{}", error),
+        }
+    };
 
     println!("\nSet | Map");
+    let results = micro_vsrg::search(&sets, &query);
     for (set_id, set) in sets.iter().enumerate() {
+        // Easiest first, like osu!'s song select
+        let mut maps: Vec<_> = results.iter()
+            .filter(|(result_set_id, _)| *result_set_id == set_id)
+            .map(|(_, map_id)| (*map_id, &set.maps[*map_id], set.maps[*map_id].difficulty().stars))
+            .collect();
+        if maps.is_empty() {
+            continue;
+        }
+        maps.sort_by(|a, b| a.2.total_cmp(&b.2));
         println!("{} ~~~~~~~", set_id);
-        for (map_id, map) in set.maps.iter().enumerate() {
-            println!("    {}: {:#?}", map_id, map.full_title);
+        for (map_id, map, stars) in maps {
+            let density = map.density(DENSITY_WIDTH);
+            let peak = density.iter().copied().max().unwrap_or(0).max(1);
+            let graph: String = density.iter().map(|notes| DENSITY_BARS[notes*(DENSITY_BARS.len()-1)/peak]).collect();
+            println!("    {}: {:#?} by {} ({:.2}*, {}k, mapped by {}) {}", map_id, map.full_title, map.metadata.artist_unicode, stars, map.column_count, map.metadata.creator, graph);
         }
     }
     print!("Select a map {{set_id}},{{map_id}}: ");
@@ -68,25 +123,115 @@ fn main() {
     let set = sets.get(parts[0].parse::<usize>().expect("couldn't parse set id")).expect("couldn't get set");
     let map = set.maps.get(parts[1].parse::<usize>().expect("couldn't parse map id")).expect("couldn't get map");
 
+    let converted;
+    let map = if map.column_count > p1leds.len() {
+        println!("Converting {}k to the cabinet's {} lanes.", map.column_count, p1leds.len());
+        converted = map.convert_columns(p1leds.len(), ColumnStrategy::Remap).expect("cabinet has a supported lane count");
+        &converted
+    } else {
+        map
+    };
+
+    print!("Column mod: [m]irror, [r]andom or [s]huffle (blank for none): ");
+    stdout.flush().unwrap();
+    let mut input = String::new();
+    stdin.read_line(&mut input).unwrap();
+    let seed = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+    let modded;
+    let map = match input.trim() {
+        "m" => {
+            modded = map.mirror();
+            &modded
+        },
+        "r" => {
+            modded = map.random(seed);
+            &modded
+        },
+        "s" => {
+            modded = map.shuffle(seed);
+            &modded
+        },
+        _ => map,
+    };
+
+    print!("Thin the map out for robots? [y/N]: ");
+    stdout.flush().unwrap();
+    let mut input = String::new();
+    stdin.read_line(&mut input).unwrap();
+    let thinned;
+    let map = if input.trim().eq_ignore_ascii_case("y") {
+        let removed;
+        (thinned, removed) = map.for_robots(&ROBOT_LIMITS);
+        println!("Removed {} note(s).", removed.len());
+        &thinned
+    } else {
+        map
+    };
+
+    print!("Rate {{{}-{}}} (blank for 1): ", micro_vsrg::MIN_RATE, micro_vsrg::MAX_RATE);
+    stdout.flush().unwrap();
+    let mut input = String::new();
+    stdin.read_line(&mut input).unwrap();
+    let rate = if input.trim().is_empty() {
+        1.0
+    } else {
+        input.trim().parse::<f64>().expect("couldn't parse rate")
+    };
+    let mut keep_pitch = false;
+    let rated;
+    let map = if rate == 1.0 {
+        map
+    } else {
+        print!("Keep the audio's pitch? [y/N]: ");
+        stdout.flush().unwrap();
+        let mut input = String::new();
+        stdin.read_line(&mut input).unwrap();
+        keep_pitch = input.trim().eq_ignore_ascii_case("y");
+        rated = map.with_rate(rate).expect("rate is out of range");
+        &rated
+    };
+
     println!("Starting... {}", map.full_title);
 
-    let audio = set.files.get(&map.audio_file_name).expect("couldn't get map's audio file").clone();
     let (_stream, stream_handle) = rodio::OutputStream::try_default().unwrap();
     let sink = rodio::Sink::try_new(&stream_handle).unwrap();
-    let source = rodio::Decoder::new(Cursor::new(audio)).expect("failed to create decoder");
     sink.pause();
-    sink.append(source.delay(std::time::Duration::from_millis(map.audio_lead_in.try_into().unwrap())));
+    // BMS charts only have samples
+    if !map.audio_file_name.is_empty() {
+        let audio = set.files.read(&map.audio_file_name).expect("couldn't get map's audio file");
+        let source = rodio::Decoder::new(Cursor::new(audio)).expect("failed to create decoder");
+        sink.append(at_rate(source.convert_samples(), rate, keep_pitch).delay(std::time::Duration::from_millis(map.audio_lead_in.try_into().unwrap())));
+    }
+
+    // Decode every sample up front so playing one doesn't stall the loop.
+    // Buffered sources share their decoded frames, so clones are cheap.
+    let mut samples = HashMap::new();
+    for sample in map.samples.iter() {
+        if !samples.contains_key(sample.file_name.as_str()) {
+            let source = set.files.read(&sample.file_name)
+                .map_err(|error| error.to_string())
+                .and_then(|bytes| rodio::Decoder::new(Cursor::new(bytes)).map_err(|error| error.to_string()));
+            match source {
+                Ok(source) => {
+                    let source = source.convert_samples::<f32>().buffered();
+                    source.clone().for_each(drop);
+                    samples.insert(sample.file_name.as_str(), source);
+                },
+                Err(error) => eprintln!("Skipping sample {:#?}: {}", sample.file_name, error),
+            }
+        }
+    }
+    let mut next_sample = 0;
 
-    let mut player_1_notes = [0; 4];
+    let hit_windows = map.hit_windows();
+    let mut player_1_notes = vec![0; map.column_count];
     let mut player_1_hit: usize = 0;
     let mut player_1_missed: usize = 0;
-    let mut _player_2_notes = [0; 4];
+    let mut player_1_holding = vec![false; map.column_count];
+    let mut _player_2_notes = vec![0; map.column_count];
 
     // Setup button timers
-    let mut last_p1b1_pressed = Instant::now();
-    let mut last_p1b2_pressed = last_p1b1_pressed.clone();
-    let mut last_p1b3_pressed = last_p1b1_pressed.clone();
-    let mut last_p1b4_pressed = last_p1b1_pressed.clone();
+    let mut last_p1b_pressed = vec![Instant::now(); map.column_count];
     std::thread::sleep(BUTTON_DEBOUNCING); // sleep to ensure instants are back far enough. TODO: figure out how to set instances to long ago
 
     let timer = std::time::Instant::now();
@@ -94,112 +239,87 @@ fn main() {
 
     loop {
         let time: isize = timer.elapsed().as_millis().try_into().unwrap();
-        let mut done = true;
-        while let Some(p1c1) = map.notes[0].get(player_1_notes[0]) {
-            done = false;
-            let p1c1: isize = (*p1c1).try_into().unwrap();
-            let diff = p1c1-time;
-            if diff < HIT_RANGE && diff > -HIT_RANGE {
-                p1led1.set_high();
-                let p1b1_level = p1b1.read();
-                if p1b1_level==Level::High {
-                    if last_p1b1_pressed.elapsed() >= BUTTON_DEBOUNCING {
-                        p1led1.set_low();
-                        player_1_hit += 1;
-                        player_1_notes[0] += 1;
-                        println!("P1B1 PRESSED!");
-                    }
-                    last_p1b1_pressed = Instant::now();
-                }
-                break;
-            } else if diff < -HIT_RANGE {
-                p1led1.set_low();
-                player_1_missed += 1;
-                println!("P1B1 MISSED!");
-                player_1_notes[0] += 1;
-            } else {
-                break;
+        while let Some(sample) = map.samples.get(next_sample).filter(|sample| sample.time as isize <= time) {
+            if let Some(source) = samples.get(sample.file_name.as_str()) {
+                stream_handle.play_raw(at_rate(source.clone(), rate, keep_pitch)).ok();
             }
+            next_sample += 1;
         }
-
-        while let Some(p1c2) = map.notes[1].get(player_1_notes[1]) {
-            done = false;
-            let p1c2: isize = (*p1c2).try_into().unwrap();
-            let diff = p1c2-time;
-            if diff < HIT_RANGE && diff > -HIT_RANGE {
-                p1led2.set_high();
-                let p1b2_level = p1b2.read();
-                if p1b2_level==Level::High {
-                    if last_p1b2_pressed.elapsed() >= BUTTON_DEBOUNCING {
-                        p1led2.set_low();
-                        player_1_hit += 1;
-                        player_1_notes[1] += 1;
-                        println!("P1B2 PRESSED!");
+        let mut done = true;
+        for column in 0..map.column_count {
+            let p1led = &mut p1leds[column];
+            let p1b = &p1bs[column];
+            while let Some(p1c) = map.notes[column].get(player_1_notes[column]) {
+                done = false;
+                if player_1_holding[column] {
+                    let p1c_end: isize = p1c.end_time.expect("only hold notes are held").try_into().unwrap();
+                    if p1b.read()==Level::High {
+                        last_p1b_pressed[column] = Instant::now();
                     }
-                    last_p1b2_pressed = Instant::now();
-                }
-                break;
-            } else if diff < -HIT_RANGE {
-                p1led2.set_low();
-                player_1_missed += 1;
-                println!("P1B2 MISSED!");
-                player_1_notes[1] += 1;
-            } else {
-                break;
-            }
-        }
-
-        while let Some(p1c3) = map.notes[2].get(player_1_notes[2]) {
-            done = false;
-            let p1c3: isize = (*p1c3).try_into().unwrap();
-            let diff = p1c3-time;
-            if diff < HIT_RANGE && diff > -HIT_RANGE {
-                p1led3.set_high();
-                let p1b3_level = p1b3.read();
-                if p1b3_level==Level::High {
-                    if last_p1b3_pressed.elapsed() >= BUTTON_DEBOUNCING {
-                        p1led3.set_low();
+                    // The release is only noticed once the button has been low
+                    // for BUTTON_DEBOUNCING, so it's judged from the last time
+                    // it was seen held
+                    let p1b_held: isize = last_p1b_pressed[column].duration_since(timer).as_millis().try_into().unwrap();
+                    if p1b_held >= p1c_end {
+                        // Holding through the tail counts as releasing on it
+                        p1led.set_low();
                         player_1_hit += 1;
-                        player_1_notes[2] += 1;
-                        println!("P1B3 PRESSED!");
+                        player_1_holding[column] = false;
+                        player_1_notes[column] += 1;
+                        println!("P1B{} RELEASED {}!", column+1, Judgement::Perfect);
+                    } else if last_p1b_pressed[column].elapsed() >= BUTTON_DEBOUNCING {
+                        p1led.set_low();
+                        match hit_windows.judge((p1c_end-p1b_held) as f64) {
+                            Some(Judgement::Miss) | None => {
+                                player_1_missed += 1;
+                                println!("P1B{} RELEASED EARLY!", column+1);
+                            },
+                            Some(judgement) => {
+                                player_1_hit += 1;
+                                println!("P1B{} RELEASED {}!", column+1, judgement);
+                            }
+                        }
+                        player_1_holding[column] = false;
+                        player_1_notes[column] += 1;
                     }
-                    last_p1b3_pressed = Instant::now();
+                    break;
                 }
-                break;
-            } else if diff < -HIT_RANGE {
-                p1led3.set_low();
-                player_1_missed += 1;
-                println!("P1B3 MISSED!");
-                player_1_notes[2] += 1;
-            } else {
-                break;
-            }
-        }
 
-        while let Some(p1c4) = map.notes[3].get(player_1_notes[3]) {
-            done = false;
-            let p1c4: isize = (*p1c4).try_into().unwrap();
-            let diff = p1c4-time;
-            if diff < HIT_RANGE && diff > -HIT_RANGE {
-                p1led4.set_high();
-                let p1b4_level = p1b4.read();
-                if p1b4_level==Level::High {
-                    if last_p1b4_pressed.elapsed() >= BUTTON_DEBOUNCING {
-                        p1led4.set_low();
-                        player_1_hit += 1;
-                        player_1_notes[3] += 1;
-                        println!("P1B4 PRESSED!");
+                let p1c_time: isize = p1c.time.try_into().unwrap();
+                let diff = (p1c_time-time) as f64;
+                if diff < -hit_windows.meh {
+                    p1led.set_low();
+                    // A missed hold note also misses its tail
+                    player_1_missed += if p1c.is_hold() { 2 } else { 1 };
+                    println!("P1B{} MISSED!", column+1);
+                    player_1_notes[column] += 1;
+                } else if diff <= LED_LEAD {
+                    p1led.set_high();
+                    let p1b_level = p1b.read();
+                    if p1b_level==Level::High {
+                        // Presses before the note's hit windows are ignored
+                        if let Some(judgement) = hit_windows.judge(diff).filter(|_| last_p1b_pressed[column].elapsed() >= BUTTON_DEBOUNCING) {
+                            if judgement == Judgement::Miss {
+                                p1led.set_low();
+                                player_1_missed += if p1c.is_hold() { 2 } else { 1 };
+                                player_1_notes[column] += 1;
+                            } else if p1c.is_hold() {
+                                // Keep the led lit until the tail is released
+                                player_1_hit += 1;
+                                player_1_holding[column] = true;
+                            } else {
+                                p1led.set_low();
+                                player_1_hit += 1;
+                                player_1_notes[column] += 1;
+                            }
+                            println!("P1B{} {}!", column+1, judgement);
+                        }
+                        last_p1b_pressed[column] = Instant::now();
                     }
-                    last_p1b4_pressed = Instant::now();
+                    break;
+                } else {
+                    break;
                 }
-                break;
-            } else if diff < -HIT_RANGE {
-                p1led4.set_low();
-                player_1_missed += 1;
-                println!("P1B4 MISSED!");
-                player_1_notes[3] += 1;
-            } else {
-                break;
             }
         }
 
@@ -208,5 +328,19 @@ fn main() {
         } 
     }
 
-    println!("Accuracy: {}/{}", player_1_hit, player_1_hit+&player_1_missed);
-}
\ No newline at end of file
+    println!("Accuracy: {}/{}", player_1_hit, player_1_hit+player_1_missed);
+}
+
+/// Plays `source` at `rate` times speed, either raising the pitch with it or
+/// time-stretching to keep the pitch.
+fn at_rate<S>(source: S, rate: f64, keep_pitch: bool) -> Box<dyn Source<Item = f32> + Send> where
+    S: Source<Item = f32> + Send + 'static
+{
+    if rate == 1.0 {
+        Box::new(source)
+    } else if keep_pitch {
+        Box::new(TimeStretch::new(source, rate))
+    } else {
+        Box::new(source.speed(rate as f32))
+    }
+}
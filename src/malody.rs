@@ -0,0 +1,271 @@
+use std::{
+    io::Read,
+    sync::OnceLock,
+};
+
+use serde::Deserialize;
+
+use crate::{
+    DEFAULT_DIFFICULTY,
+    Diagnostic,
+    full_title,
+    Map,
+    MAX_COLUMN_COUNT,
+    Metadata,
+    Note,
+    ParseError,
+    sibling_file_name,
+    TimingPoint,
+    Warning,
+    timing::BeatTiming,
+};
+
+/// `meta.mode` of key mode charts.
+const KEY_MODE: u32 = 0;
+/// `type` of the note that places the song.
+const SOUND_NOTE: u32 = 1;
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct Mc {
+    meta: McMeta,
+    time: Vec<McTime>,
+    effect: Vec<McEffect>,
+    note: Vec<McNote>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct McMeta {
+    creator: String,
+    version: String,
+    mode: u32,
+    song: McSong,
+    mode_ext: McModeExt,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct McSong {
+    title: Option<String>,
+    artist: String,
+    /// Titles in their original script, `title` is usually romanized.
+    titleorg: Option<String>,
+    artistorg: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct McModeExt {
+    column: usize,
+}
+
+/// `[beat, numerator, denominator]`, so `[1, 1, 2]` is beat 1.5.
+type Beat = [u32; 3];
+
+#[derive(Deserialize)]
+struct McTime {
+    beat: Beat,
+    bpm: f64,
+}
+
+#[derive(Deserialize)]
+struct McEffect {
+    beat: Beat,
+    scroll: f64,
+}
+
+#[derive(Deserialize)]
+struct McNote {
+    beat: Beat,
+    endbeat: Option<Beat>,
+    column: Option<usize>,
+    sound: Option<String>,
+    /// Milliseconds into the song at `beat`, only on the sound note.
+    #[serde(default)]
+    offset: f64,
+    #[serde(default, rename = "type")]
+    kind: u32,
+}
+
+/// Parses a .mc file. Only key mode charts are supported.
+pub(crate) fn read<R>(file_name: &str, reader: R, warnings: &mut Vec<Diagnostic<Warning>>) -> Result<Map, Diagnostic<ParseError>> where
+    R: Read
+{
+    let mc: Mc = serde_json::from_reader(reader).map_err(|error| {
+        Diagnostic::at(Some(error.line()), ParseError::InvalidDocument(error.to_string()))
+    })?;
+
+    if mc.meta.mode != KEY_MODE {
+        return Err(Diagnostic::at(None, ParseError::UnsupportedMode(mc.meta.mode.to_string())));
+    }
+    let column_count = mc.meta.mode_ext.column;
+    if column_count == 0 || column_count > MAX_COLUMN_COUNT {
+        return Err(Diagnostic::at(None, ParseError::UnsupportedColumnCount(column_count.to_string())));
+    }
+    let song = mc.meta.song;
+    let title = song.title.or(song.titleorg.clone()).ok_or_else(|| Diagnostic::at(None, ParseError::MissingTitle))?;
+
+    let mut timing = BeatTiming {
+        offset: 0.0,
+        bpms: mc.time.iter().map(|time| (to_beat(time.beat), time.bpm)).collect(),
+        stops: Vec::new(),
+        delays: Vec::new(),
+    };
+    if timing.bpms.is_empty() || timing.bpms.iter().any(|(_, bpm)| *bpm <= 0.0 || !bpm.is_finite()) {
+        return Err(Diagnostic::at(None, ParseError::InvalidNumber {
+            field: "bpm",
+            value: format!("{:?}", timing.bpms),
+        }));
+    }
+    timing.bpms.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    // Beat 0 is at 0ms until the song is placed
+    let (sound, notes): (Vec<McNote>, Vec<McNote>) = mc.note.into_iter().partition(|note| note.kind == SOUND_NOTE);
+    let sound = sound.into_iter().find(|note| note.sound.is_some()).ok_or_else(|| Diagnostic::at(None, ParseError::MissingAudioFile))?;
+    timing.offset = -timing.time_at(to_beat(sound.beat)) - sound.offset;
+    let audio_file_name = sibling_file_name(file_name, &sound.sound.unwrap_or_default());
+
+    let mut columns = vec![Vec::new(); column_count];
+    for note in notes {
+        let column = match note.column {
+            Some(column) if column < column_count => column,
+            // Lanes are 1-based in the warning
+            Some(column) => {
+                warnings.push(Diagnostic::at(None, Warning::LaneOutOfRange(column+1)));
+                continue;
+            },
+            None => continue,
+        };
+        let time = timing.time_at(to_beat(note.beat));
+        let end_time = note.endbeat.map(|beat| timing.time_at(to_beat(beat)));
+        if time < 0.0 {
+            warnings.push(Diagnostic::at(None, Warning::NoteBeforeAudio));
+            continue;
+        }
+        let end_time = match end_time {
+            Some(end_time) if end_time < time => {
+                warnings.push(Diagnostic::at(None, Warning::HoldEndsBeforeStart));
+                None
+            },
+            end_time => end_time,
+        };
+        columns[column].push(Note {
+            time: time.round() as usize,
+            end_time: end_time.map(|end_time| end_time.round() as usize),
+        });
+    }
+    for column in columns.iter_mut() {
+        column.sort_by_key(|note| note.time);
+    }
+
+    let mut timing_points = timing.timing_points(4);
+    for effect in mc.effect {
+        if effect.scroll <= 0.0 || !effect.scroll.is_finite() {
+            continue;
+        }
+        timing_points.push(TimingPoint {
+            time: timing.time_at(to_beat(effect.beat)),
+            beat_length: -100.0/effect.scroll,
+            meter: 4,
+            uninherited: false,
+            kiai: false,
+        });
+    }
+    timing_points.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+    let title_unicode = song.titleorg.unwrap_or_else(|| title.clone());
+    let artist_unicode = song.artistorg.unwrap_or_else(|| song.artist.clone());
+    Ok(Map {
+        format_version: 0,
+        audio_file_name,
+        audio_lead_in: 0,
+        full_title: full_title(&mc.meta.version, &title_unicode),
+        metadata: Metadata {
+            title,
+            title_unicode,
+            artist: song.artist,
+            artist_unicode,
+            creator: mc.meta.creator,
+            version: mc.meta.version,
+            source: String::new(),
+            tags: Vec::new(),
+            beatmap_id: None,
+            beatmap_set_id: None,
+        },
+        column_count,
+        hp_drain_rate: DEFAULT_DIFFICULTY,
+        overall_difficulty: DEFAULT_DIFFICULTY,
+        notes: columns,
+        timing_points,
+        samples: Vec::new(),
+        checksum: String::new(),
+        audio_checksum: OnceLock::new(),
+    })
+}
+
+fn to_beat([whole, numerator, denominator]: Beat) -> f64 {
+    if denominator == 0 {
+        f64::from(whole)
+    } else {
+        f64::from(whole) + f64::from(numerator)/f64::from(denominator)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FileReport;
+
+    /// A 4K chart with `time` as its tempos, `sound` as its sound note and
+    /// `notes`.
+    fn parse(time: &str, sound: &str, notes: &str) -> Map {
+        let mc = format!(r#"{{
+            "meta": {{"creator": "Mapper", "version": "4K Hard", "mode": 0, "song": {{"title": "Song", "artist": "Artist"}}, "mode_ext": {{"column": 4}}}},
+            "time": [{}],
+            "note": [{}, {}]
+        }}"#, time, sound, notes);
+        let (map, report) = FileReport::parse("0/1.mc", mc.as_bytes(), read);
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+        map.unwrap()
+    }
+
+    #[test]
+    fn sound_note_offset_moves_beat_zero() {
+        let map = parse(
+            r#"{"beat": [0, 0, 1], "bpm": 120}"#,
+            r#"{"beat": [0, 0, 1], "sound": "song.ogg", "offset": 100, "type": 1}"#,
+            r#"{"beat": [1, 0, 1], "column": 0}"#,
+        );
+        assert_eq!(map.audio_file_name, "0/song.ogg");
+        assert_eq!(map.full_title, "4K Hard - Song");
+        // The song starts 100ms into beat 0, so beat 1 is 100ms sooner
+        assert_eq!(map.notes[0][0].time, 400);
+    }
+
+    #[test]
+    fn sound_note_beat_starts_the_song() {
+        let map = parse(
+            r#"{"beat": [0, 0, 1], "bpm": 120}"#,
+            r#"{"beat": [1, 0, 1], "sound": "song.ogg", "type": 1}"#,
+            r#"{"beat": [2, 0, 1], "column": 0}"#,
+        );
+        assert_eq!(map.notes[0][0].time, 500);
+    }
+
+    #[test]
+    fn beats_are_split_into_fractions() {
+        let map = parse(
+            r#"{"beat": [0, 0, 1], "bpm": 120}, {"beat": [4, 0, 1], "bpm": 240}"#,
+            r#"{"beat": [0, 0, 1], "sound": "song.ogg", "type": 1}"#,
+            r#"{"beat": [1, 1, 3], "column": 1}, {"beat": [2, 0, 1], "endbeat": [4, 2, 3], "column": 2}"#,
+        );
+        // A third of a beat at 120 BPM is 166.67ms
+        assert_eq!(map.notes[1][0].time, 667);
+        // Two thirds of a beat at 240 BPM is 166.67ms
+        assert_eq!(map.notes[2], vec![Note {
+            time: 1000,
+            end_time: Some(2167),
+        }]);
+    }
+}
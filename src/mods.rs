@@ -0,0 +1,120 @@
+use crate::{
+    full_title,
+    Map,
+    Note,
+};
+
+/// Slowest rate [`Map::with_rate`] allows.
+pub const MIN_RATE: f64 = 0.5;
+/// Fastest rate [`Map::with_rate`] allows.
+pub const MAX_RATE: f64 = 2.0;
+
+impl Map {
+    /// A copy of the map for playing at `rate` times speed (1.5 is 1.5x).
+    /// Notes, samples, timing points and the lead-in are moved to where they
+    /// fall when the audio is played at that rate, and tempos are scaled to
+    /// match. Hit windows stay the same.
+    ///
+    /// `None` when `rate` isn't between [`MIN_RATE`] and [`MAX_RATE`].
+    pub fn with_rate(&self, rate: f64) -> Option<Map> {
+        if !(MIN_RATE..=MAX_RATE).contains(&rate) {
+            return None;
+        }
+        let scale = |time: usize| (time as f64/rate).round() as usize;
+        let mut map = self.clone();
+        map.metadata.version = format!("{} ({}x)", self.metadata.version, rate);
+        map.full_title = full_title(&map.metadata.version, &map.metadata.title_unicode);
+        map.audio_lead_in = scale(self.audio_lead_in);
+        for note in map.notes.iter_mut().flatten() {
+            note.time = scale(note.time);
+            note.end_time = note.end_time.map(scale);
+        }
+        for sample in map.samples.iter_mut() {
+            sample.time = scale(sample.time);
+        }
+        for point in map.timing_points.iter_mut() {
+            point.time /= rate;
+            // Inherited points are relative to the tempo, so they stay
+            if point.uninherited {
+                point.beat_length /= rate;
+            }
+        }
+        Some(map)
+    }
+
+    /// A copy of the map with the columns in reverse order.
+    pub fn mirror(&self) -> Map {
+        self.with_notes("Mirror", self.notes.iter().rev().cloned().collect())
+    }
+
+    /// A copy of the map with its columns in a random order. The same `seed`
+    /// always gives the same order.
+    pub fn random(&self, seed: u64) -> Map {
+        let mut random = SplitMix64(seed);
+        let mut columns: Vec<usize> = (0..self.column_count).collect();
+        // Fisher-Yates
+        for index in (1..columns.len()).rev() {
+            columns.swap(index, random.below(index + 1));
+        }
+        let notes = columns.iter().map(|column| self.notes[*column].clone()).collect();
+        self.with_notes(&format!("Random #{}", seed), notes)
+    }
+
+    /// A copy of the map with every note moved to a random column. Notes
+    /// only go where they can be hit: not on top of a hold, and not in a
+    /// column that had a note the row before unless there's no other. The
+    /// same `seed` always gives the same map.
+    pub fn shuffle(&self, seed: u64) -> Map {
+        let mut random = SplitMix64(seed);
+        let mut notes: Vec<Vec<Note>> = vec![Vec::new(); self.column_count];
+        let mut previous_row: Vec<usize> = Vec::new();
+        for row in self.note_rows() {
+            let time = row[0].1.time;
+            let mut row_columns = Vec::new();
+            for (_, note) in row {
+                let free: Vec<usize> = (0..self.column_count)
+                    .filter(|column| !row_columns.contains(column))
+                    .filter(|column| notes[*column].last().is_none_or(|last| last.end_time.unwrap_or(last.time) < time))
+                    .collect();
+                let not_jacks: Vec<usize> = free.iter().copied().filter(|column| !previous_row.contains(column)).collect();
+                let choices = if not_jacks.is_empty() { &free } else { &not_jacks };
+                // A row never has more notes and holds than there are columns
+                let Some(column) = choices.get(random.below(choices.len().max(1))).copied() else {
+                    continue;
+                };
+                notes[column].push(note);
+                row_columns.push(column);
+            }
+            previous_row = row_columns;
+        }
+        self.with_notes(&format!("Shuffle #{}", seed), notes)
+    }
+
+    /// A copy of the map with `notes` and `name` after its version.
+    fn with_notes(&self, name: &str, notes: Vec<Vec<Note>>) -> Map {
+        let mut map = self.clone();
+        map.metadata.version = format!("{} ({})", self.metadata.version, name);
+        map.full_title = full_title(&map.metadata.version, &map.metadata.title_unicode);
+        map.notes = notes;
+        map
+    }
+}
+
+/// Small seeded random number generator, so mods come out the same on every
+/// machine.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number from 0 up to (not including) `bound`.
+    fn below(&mut self, bound: usize) -> usize {
+        (self.next() % bound as u64) as usize
+    }
+}
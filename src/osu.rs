@@ -0,0 +1,703 @@
+use std::{
+    io::{
+        self,
+        BufRead,
+        BufReader,
+        Read,
+        Write,
+    },
+    sync::OnceLock,
+};
+
+use crate::{
+    DEFAULT_DIFFICULTY,
+    Diagnostic,
+    full_title,
+    Map,
+    MAX_COLUMN_COUNT,
+    Metadata,
+    non_empty,
+    Note,
+    ParseError,
+    parse_number,
+    Sample,
+    Section,
+    TimingPoint,
+    Warning,
+};
+
+const MIN_FORMAT_VERSION: u32 = 3;
+const MAX_FORMAT_VERSION: u32 = 14;
+/// Written by osu!lazer, which stores times with fractional milliseconds.
+const LAZER_FORMAT_VERSION: u32 = 128;
+/// Maps before v5 were timed against an audio engine that played 24ms late.
+const EARLY_VERSION_TIMING_OFFSET: f64 = 24.0;
+
+// Hit object type bits
+const HIT_CIRCLE: u32 = 1;
+const HOLD_NOTE: u32 = 128;
+
+// Timing point effect bits
+const KIAI: u32 = 1;
+
+struct HitObject {
+    line: usize,
+    x: usize,
+    note: Note,
+}
+
+/// Accumulates the state of a .osu file while it is read line by line.
+struct OsuParser {
+    audio_file_name: Option<String>,
+    audio_lead_in: usize,
+    mode: Option<usize>,
+    title: Option<String>,
+    title_unicode: Option<String>,
+    artist: Option<String>,
+    artist_unicode: Option<String>,
+    version: Option<String>,
+    /// Metadata that doesn't need a fallback.
+    metadata: Metadata,
+    column_count: Option<usize>,
+    hp_drain_rate: f32,
+    overall_difficulty: f32,
+    hit_objects: Vec<HitObject>,
+    timing_points: Vec<TimingPoint>,
+    samples: Vec<Sample>,
+    format_version: u32,
+    section: Option<Section>,
+    /// Inside a section this parser doesn't know, whose lines are skipped.
+    in_unknown_section: bool,
+    line: usize,
+    warnings: Vec<Diagnostic<Warning>>,
+}
+
+/// Parses a single .osu file.
+pub(crate) fn read<R>(_file_name: &str, reader: R, warnings: &mut Vec<Diagnostic<Warning>>) -> Result<Map, Diagnostic<ParseError>> where
+    R: Read
+{
+    let mut parser = OsuParser {
+        audio_file_name: None,
+        audio_lead_in: 0,
+        mode: None,
+        title: None,
+        title_unicode: None,
+        artist: None,
+        artist_unicode: None,
+        version: None,
+        metadata: Metadata::default(),
+        column_count: None,
+        hp_drain_rate: DEFAULT_DIFFICULTY,
+        overall_difficulty: DEFAULT_DIFFICULTY,
+        hit_objects: Vec::new(),
+        timing_points: Vec::new(),
+        samples: Vec::new(),
+        section: None,
+        format_version: 0,
+        in_unknown_section: false,
+        line: 0,
+        warnings: Vec::new(),
+    };
+
+    let result = parser.read(reader).and_then(|_| parser.finish());
+    warnings.append(&mut parser.warnings);
+    result
+}
+
+impl OsuParser {
+    fn read<R>(&mut self, reader: R) -> Result<(), Diagnostic<ParseError>> where
+        R: Read
+    {
+        let mut lines = BufReader::new(reader).lines();
+        // The header is the first non-empty line and may start with a BOM
+        let header = loop {
+            self.line += 1;
+            match lines.next().transpose().map_err(|error| self.reject(error.into()))? {
+                Some(line) if line.trim_start_matches('\u{feff}').trim().is_empty() => {},
+                Some(line) => break line,
+                None => return Err(self.reject(ParseError::InvalidHeader)),
+            }
+        };
+        let version = header.trim_start_matches('\u{feff}').trim()
+            .strip_prefix("osu file format v")
+            .ok_or_else(|| self.reject(ParseError::InvalidHeader))?;
+        self.format_version = match version.parse() {
+            Ok(version @ (MIN_FORMAT_VERSION..=MAX_FORMAT_VERSION | LAZER_FORMAT_VERSION)) => version,
+            _ => return Err(self.reject(ParseError::UnsupportedFormatVersion(version.to_string()))),
+        };
+        for line in lines {
+            self.line += 1;
+            let line = line.map_err(|error| self.reject(error.into()))?;
+            self.parse_line(line.trim()).map_err(|error| self.reject(error))?;
+        }
+        Ok(())
+    }
+
+    fn parse_line(&mut self, line: &str) -> Result<(), ParseError> {
+        match line {
+            "" => {},
+            _ if line.starts_with("//") => {},
+            "[General]" => self.section = Some(Section::General),
+            "[Editor]" => self.section = Some(Section::Editor),
+            "[Metadata]" => self.section = Some(Section::Metadata),
+            "[Difficulty]" => self.section = Some(Section::Difficulty),
+            "[Events]" => self.section = Some(Section::Events),
+            "[TimingPoints]" => self.section = Some(Section::TimingPoints),
+            "[Colours]" => self.section = Some(Section::Colours),
+            "[HitObjects]" => self.section = Some(Section::HitObjects),
+            _ if line.starts_with('[') && line.ends_with(']') => {
+                self.warn(Warning::UnrecognizedSection(line.to_string()));
+                self.section = None;
+                self.in_unknown_section = true;
+            },
+            _ => match self.section {
+                None if self.in_unknown_section => {},
+                None => return Err(ParseError::UnknownTag(line.to_string())),
+                Some(section @ Section::General) => {
+                    let (key, value) = split_key_value(section, line)?;
+
+                    match key {
+                        "AudioFilename" => self.audio_file_name = Some(value.to_string()),
+                        "AudioLeadIn" => self.audio_lead_in = parse_number("AudioLeadIn", value)?,
+                        "Mode" => {
+                            if value != "3" {
+                                return Err(ParseError::UnsupportedMode(value.to_string()));
+                            } else {
+                                self.mode = Some(3);
+                            }
+                        },
+                        "SpecialStyle" => {
+                            if value != "0" {
+                                return Err(ParseError::UnsupportedSpecialStyle(value.to_string()));
+                            }
+                        },
+                        // Ignored keys
+                        "AudioHash" |
+                        "PreviewTime" |
+                        "Countdown" |
+                        "SampleSet" |
+                        "StackLeniency" |
+                        "LetterboxInBreaks" |
+                        "StoryFireInFront" |
+                        "UseSkipSprites" |
+                        "AlwaysShowPlayfield" |
+                        "OverlayPosition" |
+                        "SkinPreference" |
+                        "EpilepsyWarning" |
+                        "CountdownOffset" |
+                        "WidescreenStoryboard" |
+                        "SampleMatchPlaybackRate" => {},
+                        _ => self.warn(Warning::UnrecognizedKey(key.to_string())),
+                    }
+                },
+                Some(section @ Section::Editor) => {
+                    let (key, _value) = split_key_value(section, line)?;
+
+                    match key {
+                        // Ignored keys
+                        "Bookmarks" |
+                        "DistanceSpacing" |
+                        "BeatDivisor" |
+                        "GridSize" |
+                        "TimelineZoom" => {},
+                        _ => self.warn(Warning::UnrecognizedKey(key.to_string())),
+                    }
+                },
+                Some(section @ Section::Metadata) => {
+                    let (key, value) = split_key_value(section, line)?;
+
+                    match key {
+                        "Title" => self.title = non_empty(value),
+                        "TitleUnicode" => self.title_unicode = non_empty(value),
+                        "Artist" => self.artist = non_empty(value),
+                        "ArtistUnicode" => self.artist_unicode = non_empty(value),
+                        "Creator" => self.metadata.creator = value.to_string(),
+                        "Version" => self.version = Some(value.to_owned()),
+                        "Source" => self.metadata.source = value.to_string(),
+                        "Tags" => self.metadata.tags = value.split_whitespace().map(str::to_string).collect(),
+                        "BeatmapID" => self.metadata.beatmap_id = parse_id("BeatmapID", value)?,
+                        "BeatmapSetID" => self.metadata.beatmap_set_id = parse_id("BeatmapSetID", value)?,
+                        _ => self.warn(Warning::UnrecognizedKey(key.to_string())),
+                    }
+                },
+                Some(section @ Section::Difficulty) => {
+                    let (key, value) = split_key_value(section, line)?;
+
+                    match key {
+                        "CircleSize" => match value.trim().parse::<f64>() {
+                            Ok(count) if count.fract() == 0.0 && (1.0..=MAX_COLUMN_COUNT as f64).contains(&count) => {
+                                self.column_count = Some(count as usize);
+                            },
+                            _ => return Err(ParseError::UnsupportedColumnCount(value.to_string())),
+                        }
+                        "HPDrainRate" => self.hp_drain_rate = parse_number("HPDrainRate", value)?,
+                        "OverallDifficulty" => self.overall_difficulty = parse_number("OverallDifficulty", value)?,
+                        // Ignored keys
+                        "ApproachRate" |
+                        "SliderMultiplier" |
+                        "SliderTickRate" => {},
+                        _ => self.warn(Warning::UnrecognizedKey(key.to_string())),
+                    }
+                },
+                Some(Section::Events) => {
+                    // Sample,time,layer,"file",volume. Backgrounds, breaks
+                    // and storyboard sprites aren't needed.
+                    let parts: Vec<&str> = line.split(',').collect();
+                    if matches!(parts[0], "Sample" | "5") {
+                        if parts.len() < 4 {
+                            self.warn(Warning::IncompleteEvent);
+                            return Ok(());
+                        }
+                        // Samples are extras, so one at a time this can't
+                        // play (like before the audio starts) is left out
+                        let Ok(time) = self.parse_time("sample time", parts[1]) else {
+                            self.warn(Warning::InvalidSampleTime(parts[1].to_string()));
+                            return Ok(());
+                        };
+                        self.samples.push(Sample {
+                            time,
+                            file_name: parts[3].trim().trim_matches('"').replace('\\', "/"),
+                        });
+                    }
+                },
+                Some(Section::TimingPoints) => {
+                    // time,beatLength,meter,sampleSet,sampleIndex,volume,uninherited,effects
+                    // Everything after beatLength is optional in older maps.
+                    let parts: Vec<&str> = line.split(',').collect();
+                    if parts.len() < 2 {
+                        self.warn(Warning::IncompleteTimingPoint);
+                        return Ok(());
+                    }
+                    let time = parse_number::<f64>("timing point time", parts[0])? + self.time_offset();
+                    let beat_length: f64 = parse_number("timing point beat length", parts[1])?;
+                    let meter = match parts.get(2) {
+                        Some(meter) => parse_number("timing point meter", meter)?,
+                        None => 4,
+                    };
+                    let uninherited = match parts.get(6) {
+                        Some(uninherited) => parse_number::<u8>("timing point uninherited", uninherited)? != 0,
+                        None => true,
+                    };
+                    let effects = match parts.get(7) {
+                        Some(effects) => parse_number::<u32>("timing point effects", effects)?,
+                        None => 0,
+                    };
+                    if uninherited && (beat_length <= 0.0 || !beat_length.is_finite()) {
+                        self.warn(Warning::InvalidBeatLength(beat_length));
+                        return Ok(());
+                    }
+                    self.timing_points.push(TimingPoint {
+                        time,
+                        beat_length,
+                        meter,
+                        uninherited,
+                        kiai: effects & KIAI != 0,
+                    });
+                },
+                Some(Section::Colours) => {},
+                Some(Section::HitObjects) => {
+                    // Assumes osu!mania
+                    let mut parts = line.split(',');
+                    let x = parse_number("hit object x", parts.next().unwrap_or_default())?;
+                    let _y = parts.next();
+                    let time = match parts.next() {
+                        Some(time) => self.parse_time("hit object time", time)?,
+                        None => {
+                            self.warn(Warning::IncompleteHitObject);
+                            return Ok(());
+                        }
+                    };
+                    let kind = match parts.next() {
+                        Some(kind) => parse_number::<u32>("hit object type", kind)?,
+                        None => {
+                            self.warn(Warning::IncompleteHitObject);
+                            return Ok(());
+                        }
+                    };
+                    let end_time = if kind & HOLD_NOTE != 0 {
+                        let _hit_sound = parts.next();
+                        match parts.next().and_then(|extras| extras.split(':').next()) {
+                            Some(end_time) => {
+                                let end_time = self.parse_time("hold note end time", end_time)?;
+                                if end_time < time {
+                                    self.warn(Warning::HoldEndsBeforeStart);
+                                    None
+                                } else {
+                                    Some(end_time)
+                                }
+                            },
+                            None => {
+                                self.warn(Warning::IncompleteHitObject);
+                                return Ok(());
+                            }
+                        }
+                    } else if kind & HIT_CIRCLE != 0 {
+                        None
+                    } else {
+                        self.warn(Warning::UnsupportedHitObjectType(kind));
+                        return Ok(());
+                    };
+                    self.hit_objects.push(HitObject {
+                        line: self.line,
+                        x,
+                        note: Note {
+                            time,
+                            end_time,
+                        },
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<Map, Diagnostic<ParseError>> {
+        let audio_file_name = self.audio_file_name.take().ok_or_else(|| missing(Section::General, ParseError::MissingAudioFile))?;
+        if self.mode != Some(3) {
+            return Err(missing(Section::General, ParseError::NotMania));
+        }
+        // Either spelling of the title is enough
+        let (title, title_unicode) = match (self.title.take(), self.title_unicode.take()) {
+            (Some(title), Some(title_unicode)) => (title, title_unicode),
+            (Some(title), None) => (title.clone(), title),
+            (None, Some(title_unicode)) => (title_unicode.clone(), title_unicode),
+            (None, None) => return Err(missing(Section::Metadata, ParseError::MissingTitle)),
+        };
+        let (artist, artist_unicode) = match (self.artist.take(), self.artist_unicode.take()) {
+            (Some(artist), Some(artist_unicode)) => (artist, artist_unicode),
+            (Some(artist), None) => (artist.clone(), artist),
+            (None, Some(artist_unicode)) => (artist_unicode.clone(), artist_unicode),
+            (None, None) => (String::new(), String::new()),
+        };
+        let version = self.version.take().ok_or_else(|| missing(Section::Metadata, ParseError::MissingVersion))?;
+        let column_count = self.column_count.ok_or_else(|| missing(Section::Difficulty, ParseError::MissingColumnCount))?;
+
+        let mut notes = vec![Vec::new(); column_count];
+        for hit_object in std::mem::take(&mut self.hit_objects) {
+            let column = hit_object.x*column_count/512;
+            if column < column_count {
+                notes[column].push(hit_object.note);
+            } else {
+                self.warnings.push(Diagnostic {
+                    line: Some(hit_object.line),
+                    section: Some(Section::HitObjects),
+                    reason: Warning::HitObjectOutOfColumns(hit_object.x),
+                });
+            }
+        }
+
+        let mut timing_points = std::mem::take(&mut self.timing_points);
+        timing_points.sort_by(|a, b| a.time.total_cmp(&b.time));
+        let mut samples = std::mem::take(&mut self.samples);
+        samples.sort_by_key(|sample| sample.time);
+
+        Ok(Map {
+            format_version: self.format_version,
+            audio_file_name,
+            audio_lead_in: self.audio_lead_in,
+            full_title: full_title(&version, &title_unicode),
+            metadata: Metadata {
+                title,
+                title_unicode,
+                artist,
+                artist_unicode,
+                version,
+                ..std::mem::take(&mut self.metadata)
+            },
+            column_count,
+            hp_drain_rate: self.hp_drain_rate,
+            overall_difficulty: self.overall_difficulty,
+            notes,
+            timing_points,
+            samples,
+            checksum: String::new(),
+            audio_checksum: OnceLock::new(),
+        })
+    }
+
+    /// Shift applied to every time in the file.
+    fn time_offset(&self) -> f64 {
+        if self.format_version < 5 {
+            EARLY_VERSION_TIMING_OFFSET
+        } else {
+            0.0
+        }
+    }
+
+    /// Reads a hit object time in milliseconds. Only lazer maps should have
+    /// fractional times, but they are rounded wherever they show up.
+    fn parse_time(&self, field: &'static str, value: &str) -> Result<usize, ParseError> {
+        let time = parse_number::<f64>(field, value)? + self.time_offset();
+        if time.is_finite() && time >= 0.0 {
+            Ok(time.round() as usize)
+        } else {
+            Err(ParseError::InvalidNumber {
+                field,
+                value: value.to_string(),
+            })
+        }
+    }
+
+    fn warn(&mut self, reason: Warning) {
+        self.warnings.push(Diagnostic {
+            line: Some(self.line),
+            section: self.section,
+            reason,
+        });
+    }
+
+    fn reject(&self, reason: ParseError) -> Diagnostic<ParseError> {
+        Diagnostic {
+            line: Some(self.line),
+            section: self.section,
+            reason,
+        }
+    }
+
+}
+
+/// Rejection for a value that was never found, so it has no line.
+fn missing(section: Section, reason: ParseError) -> Diagnostic<ParseError> {
+    Diagnostic {
+        line: None,
+        section: Some(section),
+        reason,
+    }
+}
+
+/// Unsubmitted maps use 0 or -1 for their ids.
+fn parse_id(field: &'static str, value: &str) -> Result<Option<u32>, ParseError> {
+    let id: i64 = parse_number(field, value)?;
+    Ok(u32::try_from(id).ok().filter(|id| *id > 0))
+}
+
+/// Splits a `key: value` line. Older maps leave out the space after the colon.
+fn split_key_value(section: Section, line: &str) -> Result<(&str, &str), ParseError> {
+    match line.split_once(':') {
+        Some((key, value)) => Ok((key.trim(), value.trim())),
+        None => Err(ParseError::MalformedLine {
+            section,
+            line: line.to_string(),
+        }),
+    }
+}
+
+/// Writes `map` as an osu file format v14 .osu file.
+pub(crate) fn write<W>(map: &Map, writer: W) -> io::Result<()> where
+    W: Write
+{
+    let mut writer = io::BufWriter::new(writer);
+    let metadata = &map.metadata;
+    writeln!(writer, "osu file format v{}", MAX_FORMAT_VERSION)?;
+
+    writeln!(writer, "\n[General]")?;
+    writeln!(writer, "AudioFilename: {}", map.audio_file_name)?;
+    writeln!(writer, "AudioLeadIn: {}", map.audio_lead_in)?;
+    writeln!(writer, "PreviewTime: -1")?;
+    writeln!(writer, "Mode: 3")?;
+    writeln!(writer, "SpecialStyle: 0")?;
+
+    writeln!(writer, "\n[Metadata]")?;
+    writeln!(writer, "Title:{}", metadata.title)?;
+    writeln!(writer, "TitleUnicode:{}", metadata.title_unicode)?;
+    writeln!(writer, "Artist:{}", metadata.artist)?;
+    writeln!(writer, "ArtistUnicode:{}", metadata.artist_unicode)?;
+    writeln!(writer, "Creator:{}", metadata.creator)?;
+    writeln!(writer, "Version:{}", metadata.version)?;
+    writeln!(writer, "Source:{}", metadata.source)?;
+    writeln!(writer, "Tags:{}", metadata.tags.join(" "))?;
+    writeln!(writer, "BeatmapID:{}", metadata.beatmap_id.unwrap_or(0))?;
+    writeln!(writer, "BeatmapSetID:{}", metadata.beatmap_set_id.map_or(-1, i64::from))?;
+
+    writeln!(writer, "\n[Difficulty]")?;
+    writeln!(writer, "HPDrainRate:{}", map.hp_drain_rate)?;
+    writeln!(writer, "CircleSize:{}", map.column_count)?;
+    writeln!(writer, "OverallDifficulty:{}", map.overall_difficulty)?;
+    writeln!(writer, "ApproachRate:{}", DEFAULT_DIFFICULTY)?;
+    writeln!(writer, "SliderMultiplier:1.4")?;
+    writeln!(writer, "SliderTickRate:1")?;
+
+    writeln!(writer, "\n[Events]")?;
+    for sample in map.samples.iter() {
+        writeln!(writer, "Sample,{},0,\"{}\",100", sample.time, sample.file_name)?;
+    }
+
+    writeln!(writer, "\n[TimingPoints]")?;
+    for point in map.timing_points.iter() {
+        writeln!(
+            writer,
+            "{},{},{},0,0,100,{},{}",
+            point.time,
+            point.beat_length,
+            point.meter,
+            u8::from(point.uninherited),
+            if point.kiai { KIAI } else { 0 },
+        )?;
+    }
+
+    writeln!(writer, "\n[HitObjects]")?;
+    let mut hit_objects: Vec<(usize, &Note)> = map.notes.iter()
+        .enumerate()
+        .flat_map(|(column, notes)| notes.iter().map(move |note| (column, note)))
+        .collect();
+    hit_objects.sort_by_key(|(column, note)| (note.time, *column));
+    for (column, note) in hit_objects {
+        // Middle of the column so it reads back into the same one
+        let x = (2*column + 1)*512/(2*map.column_count);
+        match note.end_time {
+            Some(end_time) => writeln!(writer, "{},192,{},{},0,{}:0:0:0:0:", x, note.time, HOLD_NOTE, end_time)?,
+            None => writeln!(writer, "{},192,{},{},0,0:0:0:0:", x, note.time, HIT_CIRCLE)?,
+        }
+    }
+
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ColumnStrategy,
+        FileReport,
+        RobotLimits,
+    };
+
+    fn parse(file_name: &str, bytes: &[u8]) -> (Option<Map>, FileReport) {
+        FileReport::parse(file_name, bytes, read)
+    }
+
+    /// A .osu file with `header` and a hold note and an inherited timing
+    /// point, starting at line 1.
+    fn osu_file(header: &str) -> String {
+        format!("{}
+[General]
+AudioFilename: audio.mp3
+Mode: 3
+
+[Metadata]
+Title:Song
+Artist:Artist
+Creator:Mapper
+Version:Hard
+Tags:a b
+
+[Difficulty]
+HPDrainRate:7
+CircleSize:4
+OverallDifficulty:8
+
+[TimingPoints]
+100,500,4,2,0,60,1,0
+1100,-50,4,2,0,60,0,1
+
+[HitObjects]
+64,192,100,1,0,0:0:0:0:
+320,192,1100,128,0,1600:0:0:0:0:
+", header)
+    }
+
+    #[test]
+    fn write_osu_reads_back_into_the_same_map() {
+        let map = Map::from_osu(format!("\u{feff}{}", osu_file("osu file format v12")).as_bytes()).unwrap();
+        assert_eq!(map.format_version, 12);
+        assert_eq!(map.notes[2], vec![Note {
+            time: 1100,
+            end_time: Some(1600),
+        }]);
+        assert!(!map.timing_points[1].uninherited);
+
+        let mut written = Vec::new();
+        map.write_osu(&mut written).unwrap();
+        let read_back = Map::from_osu(&written[..]).unwrap();
+        assert_eq!(read_back.audio_file_name, map.audio_file_name);
+        assert_eq!(read_back.audio_lead_in, map.audio_lead_in);
+        assert_eq!(read_back.full_title, map.full_title);
+        assert_eq!(read_back.metadata, map.metadata);
+        assert_eq!(read_back.column_count, map.column_count);
+        assert_eq!(read_back.hp_drain_rate, map.hp_drain_rate);
+        assert_eq!(read_back.overall_difficulty, map.overall_difficulty);
+        assert_eq!(read_back.notes, map.notes);
+        assert_eq!(read_back.timing_points, map.timing_points);
+        assert_eq!(read_back.samples, map.samples);
+    }
+
+    #[test]
+    fn converted_maps_read_back_with_the_same_full_title() {
+        let map = Map::from_osu(osu_file("osu file format v14").as_bytes()).unwrap();
+        let converted = [
+            map.convert_columns(7, ColumnStrategy::Fold).unwrap(),
+            map.for_robots(&RobotLimits {
+                column_interval: 150,
+                max_simultaneous: 2,
+                lane_switch: 100,
+            }).0,
+            map.with_rate(1.5).unwrap(),
+            map.shuffle(1),
+        ];
+        for map in converted {
+            let mut written = Vec::new();
+            map.write_osu(&mut written).unwrap();
+            assert_eq!(Map::from_osu(&written[..]).unwrap().full_title, map.full_title);
+        }
+    }
+
+    #[test]
+    fn rejections_have_reasons_and_lines() {
+        let (map, report) = parse("bad.osu", osu_file("osu file format v14").replace("CircleSize:4", "CircleSize:four").as_bytes());
+        assert!(map.is_none());
+        let rejection = report.rejection.unwrap();
+        assert!(matches!(rejection.reason, ParseError::UnsupportedColumnCount(ref count) if count == "four"));
+        assert_eq!(rejection.line, Some(15));
+        assert_eq!(rejection.section, Some(Section::Difficulty));
+
+        let (_, report) = parse("bad.osu", osu_file("osu file format v14").replace("Title:Song\n", "").as_bytes());
+        let rejection = report.rejection.unwrap();
+        assert!(matches!(rejection.reason, ParseError::MissingTitle));
+        assert_eq!(rejection.line, None);
+        assert_eq!(rejection.section, Some(Section::Metadata));
+
+        let (_, report) = parse("bad.osu", osu_file("osu file format v14").replace("Tags:a b", "Tags:a b\nGenre:Rock").as_bytes());
+        assert!(report.rejection.is_none());
+        assert_eq!(report.warnings.len(), 1);
+        assert_eq!(report.warnings[0].line, Some(12));
+        assert_eq!(report.warnings[0].reason, Warning::UnrecognizedKey("Genre".to_string()));
+    }
+
+    #[test]
+    fn samples_with_invalid_times_are_skipped() {
+        let file = osu_file("osu file format v14").replace("[TimingPoints]", "[Events]\nSample,-200,0,\"intro.wav\",70\nSample,500,0,\"hit.wav\",70\n\n[TimingPoints]");
+        let (map, report) = parse("map.osu", file.as_bytes());
+        assert_eq!(map.unwrap().samples, vec![Sample {
+            time: 500,
+            file_name: "hit.wav".to_string(),
+        }]);
+        assert_eq!(report.warnings.len(), 1);
+        assert_eq!(report.warnings[0].reason, Warning::InvalidSampleTime("-200".to_string()));
+    }
+
+    #[test]
+    fn supported_format_versions() {
+        for version in (MIN_FORMAT_VERSION..=MAX_FORMAT_VERSION).chain([LAZER_FORMAT_VERSION]) {
+            let (map, report) = parse("map.osu", osu_file(&format!("osu file format v{}", version)).as_bytes());
+            assert!(report.rejection.is_none(), "v{} was rejected", version);
+            assert_eq!(map.unwrap().format_version, version);
+        }
+        for version in ["2", "15", "127", "x"] {
+            let (map, report) = parse("map.osu", osu_file(&format!("osu file format v{}", version)).as_bytes());
+            assert!(map.is_none());
+            assert!(matches!(report.rejection.unwrap().reason, ParseError::UnsupportedFormatVersion(ref rejected) if rejected == version));
+        }
+        let (_, report) = parse("map.osu", osu_file("not an osu file").as_bytes());
+        let rejection = report.rejection.unwrap();
+        assert!(matches!(rejection.reason, ParseError::InvalidHeader));
+        assert_eq!(rejection.line, Some(1));
+    }
+
+    #[test]
+    fn early_versions_are_offset() {
+        let (map, _) = parse("map.osu", osu_file("osu file format v4").as_bytes());
+        assert_eq!(map.unwrap().notes[0][0].time, 100 + EARLY_VERSION_TIMING_OFFSET as usize);
+        let (map, _) = parse("map.osu", osu_file("osu file format v5").as_bytes());
+        assert_eq!(map.unwrap().notes[0][0].time, 100);
+    }
+}
@@ -0,0 +1,262 @@
+use std::{
+    io::Read,
+    sync::OnceLock,
+};
+
+use serde::Deserialize;
+
+use crate::{
+    DEFAULT_DIFFICULTY,
+    Diagnostic,
+    full_title,
+    Map,
+    MAX_COLUMN_COUNT,
+    Metadata,
+    Note,
+    ParseError,
+    sibling_file_name,
+    TimingPoint,
+    Warning,
+};
+
+// Quaver leaves out fields that are at their default value
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "PascalCase", default)]
+struct Qua {
+    audio_file: Option<String>,
+    mode: String,
+    title: Option<String>,
+    artist: String,
+    source: String,
+    tags: String,
+    creator: String,
+    difficulty_name: String,
+    has_scratch_key: bool,
+    timing_points: Vec<QuaTimingPoint>,
+    slider_velocities: Vec<QuaSliderVelocity>,
+    hit_objects: Vec<QuaHitObject>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "PascalCase", default)]
+struct QuaTimingPoint {
+    start_time: f64,
+    bpm: f64,
+    /// `Quadruple` or `Triple`, sometimes written as the number of beats.
+    signature: Option<serde_yaml::Value>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "PascalCase", default)]
+struct QuaSliderVelocity {
+    start_time: f64,
+    multiplier: f64,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "PascalCase", default)]
+struct QuaHitObject {
+    start_time: f64,
+    /// 1-based
+    lane: usize,
+    end_time: f64,
+}
+
+/// Parses a .qua file.
+pub(crate) fn read<R>(file_name: &str, reader: R, warnings: &mut Vec<Diagnostic<Warning>>) -> Result<Map, Diagnostic<ParseError>> where
+    R: Read
+{
+    let qua: Qua = serde_yaml::from_reader(reader).map_err(|error| {
+        Diagnostic::at(error.location().map(|location| location.line()), ParseError::InvalidDocument(error.to_string()))
+    })?;
+
+    // Keys4, Keys7, ...
+    let key_count = qua.mode.strip_prefix("Keys")
+        .and_then(|count| count.parse::<usize>().ok())
+        .ok_or_else(|| Diagnostic::at(None, ParseError::UnsupportedMode(qua.mode.clone())))?;
+    let column_count = key_count + usize::from(qua.has_scratch_key);
+    if column_count == 0 || column_count > MAX_COLUMN_COUNT {
+        return Err(Diagnostic::at(None, ParseError::UnsupportedColumnCount(column_count.to_string())));
+    }
+    let audio_file_name = qua.audio_file.ok_or_else(|| Diagnostic::at(None, ParseError::MissingAudioFile))?;
+    let audio_file_name = sibling_file_name(file_name, &audio_file_name);
+    let title = qua.title.ok_or_else(|| Diagnostic::at(None, ParseError::MissingTitle))?;
+
+    let mut notes = vec![Vec::new(); column_count];
+    for hit_object in qua.hit_objects {
+        if hit_object.lane == 0 || hit_object.lane > column_count {
+            warnings.push(Diagnostic::at(None, Warning::LaneOutOfRange(hit_object.lane)));
+            continue;
+        }
+        if hit_object.start_time < 0.0 {
+            warnings.push(Diagnostic::at(None, Warning::NoteBeforeAudio));
+            continue;
+        }
+        let time = hit_object.start_time.round() as usize;
+        let end_time = if hit_object.end_time <= 0.0 {
+            None
+        } else if hit_object.end_time < hit_object.start_time {
+            warnings.push(Diagnostic::at(None, Warning::HoldEndsBeforeStart));
+            None
+        } else {
+            Some(hit_object.end_time.round() as usize)
+        };
+        notes[hit_object.lane-1].push(Note {
+            time,
+            end_time,
+        });
+    }
+    for column in notes.iter_mut() {
+        column.sort_by_key(|note| note.time);
+    }
+
+    let mut timing_points = Vec::new();
+    for point in qua.timing_points {
+        if point.bpm <= 0.0 || !point.bpm.is_finite() {
+            warnings.push(Diagnostic::at(None, Warning::InvalidBeatLength(60_000.0/point.bpm)));
+            continue;
+        }
+        let meter = match &point.signature {
+            Some(serde_yaml::Value::String(signature)) if signature == "Triple" => 3,
+            Some(serde_yaml::Value::Number(beats)) => beats.as_u64().map_or(4, |beats| beats as u32),
+            _ => 4,
+        };
+        timing_points.push(TimingPoint {
+            time: point.start_time,
+            beat_length: 60_000.0/point.bpm,
+            meter,
+            uninherited: true,
+            kiai: false,
+        });
+    }
+    for velocity in qua.slider_velocities {
+        if velocity.multiplier.is_nan() {
+            continue;
+        }
+        // Quaver stops (0x) and reverses (negative) the scroll, which become
+        // the slowest velocity osu! has
+        let multiplier = velocity.multiplier.clamp(0.1, 10.0);
+        timing_points.push(TimingPoint {
+            time: velocity.start_time,
+            beat_length: -100.0/multiplier,
+            meter: 4,
+            uninherited: false,
+            kiai: false,
+        });
+    }
+    timing_points.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+    Ok(Map {
+        format_version: 0,
+        audio_file_name,
+        audio_lead_in: 0,
+        full_title: full_title(&qua.difficulty_name, &title),
+        metadata: Metadata {
+            title: title.clone(),
+            title_unicode: title,
+            artist: qua.artist.clone(),
+            artist_unicode: qua.artist,
+            creator: qua.creator,
+            version: qua.difficulty_name,
+            source: qua.source,
+            tags: qua.tags.split_whitespace().map(str::to_string).collect(),
+            // Quaver ids aren't osu! ids
+            beatmap_id: None,
+            beatmap_set_id: None,
+        },
+        column_count,
+        hp_drain_rate: DEFAULT_DIFFICULTY,
+        overall_difficulty: DEFAULT_DIFFICULTY,
+        notes,
+        timing_points,
+        samples: Vec::new(),
+        checksum: String::new(),
+        audio_checksum: OnceLock::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FileReport;
+
+    const QUA: &str = "AudioFile: audio.mp3
+Mode: Keys4
+Title: Song
+Artist: Artist
+Creator: Mapper
+DifficultyName: Hard
+TimingPoints:
+- StartTime: 100
+  Bpm: 120
+  Signature: Triple
+SliderVelocities:
+- StartTime: 500
+  Multiplier: 2
+HitObjects:
+- StartTime: 100
+  Lane: 1
+- StartTime: 600.4
+  Lane: 4
+  EndTime: 1100
+";
+
+    fn parse(qua: &str) -> (Option<Map>, FileReport) {
+        FileReport::parse("set/hard.qua", qua.as_bytes(), read)
+    }
+
+    #[test]
+    fn reads_qua_files() {
+        let map = parse(QUA).0.unwrap();
+        assert_eq!(map.audio_file_name, "set/audio.mp3");
+        assert_eq!(map.full_title, "Hard - Song");
+        assert_eq!(map.column_count, 4);
+        assert_eq!(map.notes[0], vec![Note {
+            time: 100,
+            end_time: None,
+        }]);
+        assert_eq!(map.notes[3], vec![Note {
+            time: 600,
+            end_time: Some(1100),
+        }]);
+        assert_eq!(map.timing_points, vec![
+            TimingPoint {
+                time: 100.0,
+                beat_length: 500.0,
+                meter: 3,
+                uninherited: true,
+                kiai: false,
+            },
+            TimingPoint {
+                time: 500.0,
+                beat_length: -50.0,
+                meter: 4,
+                uninherited: false,
+                kiai: false,
+            },
+        ]);
+
+        let scratch = QUA.replace("Mode: Keys4", "Mode: Keys7\nHasScratchKey: true");
+        assert_eq!(parse(&scratch).0.unwrap().column_count, 8);
+        let (map, report) = parse(&QUA.replace("Keys4", "Keys11"));
+        assert!(map.is_none());
+        assert!(matches!(report.rejection.unwrap().reason, ParseError::UnsupportedColumnCount(ref count) if count == "11"));
+    }
+
+    #[test]
+    fn slider_velocities_are_clamped() {
+        let qua = QUA.replace("  Multiplier: 2", "  Multiplier: 0
+- StartTime: 600
+  Multiplier: -1
+- StartTime: 700
+  Multiplier: 20
+- StartTime: 800
+  Multiplier: .nan");
+        let map = parse(&qua).0.unwrap();
+        let beat_lengths: Vec<f64> = map.timing_points.iter()
+            .filter(|point| !point.uninherited)
+            .map(|point| point.beat_length)
+            .collect();
+        assert_eq!(beat_lengths, [-1000.0, -1000.0, -10.0]);
+    }
+}
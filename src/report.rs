@@ -0,0 +1,157 @@
+use std::fmt;
+
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::{
+    ParseError,
+    Section,
+};
+
+/// Everything that went wrong (or was skipped) while loading a set, one entry
+/// per chart file.
+#[derive(Debug, Default)]
+pub struct ParseReport {
+    pub files: Vec<FileReport>,
+}
+
+impl ParseReport {
+    /// Files that did not produce a map.
+    pub fn rejected(&self) -> impl Iterator<Item = &FileReport> {
+        self.files.iter().filter(|file| file.rejection.is_some())
+    }
+
+    pub fn warning_count(&self) -> usize {
+        self.files.iter().map(|file| file.warnings.len()).sum()
+    }
+}
+
+#[derive(Debug)]
+pub struct FileReport {
+    pub file_name: String,
+    pub warnings: Vec<Diagnostic<Warning>>,
+    /// Why the file was not loaded as a map, if it wasn't.
+    pub rejection: Option<Diagnostic<ParseError>>,
+}
+
+impl FileReport {
+    /// Reads a chart file with an importer's `read`, which pushes warnings as
+    /// it goes, and makes the file's report. `None` when it was rejected.
+    pub(crate) fn parse<R, T, F>(file_name: &str, reader: R, read: F) -> (Option<T>, FileReport) where
+        F: FnOnce(&str, R, &mut Vec<Diagnostic<Warning>>) -> Result<T, Diagnostic<ParseError>>
+    {
+        let mut warnings = Vec::new();
+        let (parsed, rejection) = match read(file_name, reader, &mut warnings) {
+            Ok(parsed) => (Some(parsed), None),
+            Err(rejection) => (None, Some(rejection)),
+        };
+        (parsed, FileReport {
+            file_name: file_name.to_string(),
+            warnings,
+            rejection,
+        })
+    }
+}
+
+/// A warning or rejection along with where in the file it happened.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Diagnostic<T> {
+    /// 1-based line number. `None` when the problem is something missing from
+    /// the file as a whole.
+    pub line: Option<usize>,
+    pub section: Option<Section>,
+    pub reason: T,
+}
+
+impl<T> Diagnostic<T> {
+    /// A problem at `line` that isn't in a .osu section, which is every
+    /// problem in other formats.
+    pub(crate) fn at(line: Option<usize>, reason: T) -> Diagnostic<T> {
+        Diagnostic {
+            line,
+            section: None,
+            reason,
+        }
+    }
+}
+
+impl<T> fmt::Display for Diagnostic<T> where
+    T: fmt::Display
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.line, self.section) {
+            (Some(line), Some(section)) => write!(f, "line {} [{}]: {}", line, section, self.reason),
+            (Some(line), None) => write!(f, "line {}: {}", line, self.reason),
+            (None, Some(section)) => write!(f, "[{}]: {}", section, self.reason),
+            (None, None) => write!(f, "{}", self.reason),
+        }
+    }
+}
+
+/// Problems that don't stop a map from loading.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Warning {
+    UnrecognizedKey(String),
+    /// The section's lines are skipped.
+    UnrecognizedSection(String),
+    UnsupportedHitObjectType(u32),
+    IncompleteHitObject,
+    /// The hold note's end time is before its start, so it's read as a regular note.
+    HoldEndsBeforeStart,
+    /// The hit object's x doesn't land in any column.
+    HitObjectOutOfColumns(usize),
+    /// The note's lane (1-based) isn't one of the chart's columns.
+    LaneOutOfRange(usize),
+    IncompleteTimingPoint,
+    IncompleteEvent,
+    /// A storyboard sample with a time that isn't a number or is before 0.
+    InvalidSampleTime(String),
+    /// A chart without all of its fields.
+    IncompleteChart,
+    /// A chart for a game mode or controller that isn't supported.
+    UnsupportedChartType(String),
+    /// A row of notes shorter than the chart's column count.
+    IncompleteRow,
+    /// The note comes before the audio starts.
+    NoteBeforeAudio,
+    UnmatchedHoldTail,
+    UnterminatedHold,
+    /// An uninherited timing point with a beat length that isn't positive.
+    InvalidBeatLength(f64),
+    /// A chart without BPMs, or with BPMs that aren't positive (like
+    /// StepMania's negative BPM warps).
+    UnsupportedBpms(String),
+    /// A BMS channel for notes this game can't play, like player 2's keys.
+    UnsupportedChannel(String),
+    /// A BMS object that refers to a #BPMxx or #STOPxx that isn't defined.
+    UndefinedObject(String),
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Warning::UnrecognizedKey(key) => write!(f, "unrecognized key: {}", key),
+            Warning::UnrecognizedSection(section) => write!(f, "unrecognized section {}, skipping", section),
+            Warning::UnsupportedHitObjectType(kind) => write!(f, "hit object type {} is not supported, ignoring", kind),
+            Warning::IncompleteHitObject => f.write_str("hit object is missing fields, ignoring"),
+            Warning::HoldEndsBeforeStart => f.write_str("hold note ends before it starts, reading as a regular note"),
+            Warning::HitObjectOutOfColumns(x) => write!(f, "hit object at x {} does not fit in columns, ignoring", x),
+            Warning::LaneOutOfRange(lane) => write!(f, "note in lane {} does not fit in columns, ignoring", lane),
+            Warning::IncompleteEvent => f.write_str("event is missing fields, ignoring"),
+            Warning::InvalidSampleTime(time) => write!(f, "sample time {} is not supported, ignoring", time),
+            Warning::IncompleteTimingPoint => f.write_str("timing point is missing fields, ignoring"),
+            Warning::IncompleteChart => f.write_str("chart is missing fields, ignoring"),
+            Warning::UnsupportedChartType(kind) => write!(f, "chart type {} is not supported, ignoring", kind),
+            Warning::IncompleteRow => f.write_str("row has fewer notes than columns, ignoring"),
+            Warning::NoteBeforeAudio => f.write_str("note is before the audio starts, ignoring"),
+            Warning::UnmatchedHoldTail => f.write_str("hold tail without a head, ignoring"),
+            Warning::UnterminatedHold => f.write_str("hold head without a tail, ignoring"),
+            Warning::InvalidBeatLength(beat_length) => write!(f, "timing point beat length {} is not positive, ignoring", beat_length),
+            Warning::UnsupportedBpms(bpms) => write!(f, "chart BPMs {} are not supported, ignoring chart", bpms),
+            Warning::UnsupportedChannel(channel) => write!(f, "channel {} is not supported, ignoring", channel),
+            Warning::UndefinedObject(object) => write!(f, "{} is not defined, ignoring", object),
+        }
+    }
+}
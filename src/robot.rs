@@ -0,0 +1,100 @@
+use std::fmt;
+
+use crate::{
+    full_title,
+    Map,
+    Note,
+};
+
+/// What the robots playing a map can keep up with. Times are in
+/// milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RobotLimits {
+    /// Shortest time from a note (or the end of a hold) to the next note in
+    /// the same column.
+    pub column_interval: usize,
+    /// Most notes pressed or held at once.
+    pub max_simultaneous: usize,
+    /// Shortest time from a note to a later note in another column.
+    pub lane_switch: usize,
+}
+
+/// The limit a note was removed for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RobotLimit {
+    ColumnInterval,
+    MaxSimultaneous,
+    LaneSwitch,
+}
+
+impl fmt::Display for RobotLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            RobotLimit::ColumnInterval => "too soon after the last note in its column",
+            RobotLimit::MaxSimultaneous => "too many notes at once",
+            RobotLimit::LaneSwitch => "too soon after a note in another column",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A note [`Map::for_robots`] took out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RemovedNote {
+    pub column: usize,
+    pub note: Note,
+    pub reason: RobotLimit,
+}
+
+impl Map {
+    /// A copy of the map with notes removed until it fits in `limits`, and
+    /// the notes that were removed. Notes are kept first come first served,
+    /// and left to right within a chord, so the same map always thins the
+    /// same way.
+    pub fn for_robots(&self, limits: &RobotLimits) -> (Map, Vec<RemovedNote>) {
+        let mut notes: Vec<Vec<Note>> = vec![Vec::new(); self.column_count];
+        let mut removed = Vec::new();
+        for row in self.note_rows() {
+            let time = row[0].1.time;
+            let mut row_count = 0;
+            for (column, note) in row {
+                let held = notes.iter()
+                    .enumerate()
+                    .filter(|(other, kept)| *other != column && kept.last().is_some_and(|last| last.time < time && last.end_time.is_some_and(|end_time| end_time >= time)))
+                    .count();
+                let column_free = notes[column].last().is_none_or(|last| last.end_time.unwrap_or(last.time) + limits.column_interval <= time);
+                let switch_free = notes.iter()
+                    .enumerate()
+                    .filter(|(other, _)| *other != column)
+                    .filter_map(|(_, kept)| kept.iter().rev().find(|kept| kept.time < time))
+                    .all(|last| last.time + limits.lane_switch <= time);
+                let reason = if !column_free {
+                    Some(RobotLimit::ColumnInterval)
+                } else if held + row_count >= limits.max_simultaneous {
+                    Some(RobotLimit::MaxSimultaneous)
+                } else if !switch_free {
+                    Some(RobotLimit::LaneSwitch)
+                } else {
+                    None
+                };
+                match reason {
+                    Some(reason) => removed.push(RemovedNote {
+                        column,
+                        note,
+                        reason,
+                    }),
+                    None => {
+                        notes[column].push(note);
+                        row_count += 1;
+                    }
+                }
+            }
+        }
+
+        let mut map = self.clone();
+        map.metadata.version = format!("{} (Robot)", self.metadata.version);
+        map.full_title = full_title(&map.metadata.version, &map.metadata.title_unicode);
+        map.notes = notes;
+        (map, removed)
+    }
+}
@@ -0,0 +1,237 @@
+use std::{
+    ops::RangeInclusive,
+    str::FromStr,
+};
+
+use crate::{
+    Map,
+    ParseError,
+    parse_number,
+    Set,
+};
+
+/// What [`search`] looks for. Filters left as `None` match every map, so
+/// `Query::default()` matches everything. Can be parsed from what a player
+/// types, see [`Query::from_str`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Query {
+    /// Words that must all be in the map's title, artist, creator, tags or
+    /// version, ignoring case.
+    pub text: String,
+    pub column_count: Option<RangeInclusive<usize>>,
+    /// Milliseconds, see [`Statistics::length`](crate::Statistics::length).
+    pub length: Option<RangeInclusive<usize>>,
+    pub note_count: Option<RangeInclusive<usize>>,
+    /// Checked against [`Map::main_bpm`]. Maps without a tempo don't match.
+    pub bpm: Option<RangeInclusive<f64>>,
+    /// Checked against [`Difficulty::stars`](crate::Difficulty::stars).
+    pub stars: Option<RangeInclusive<f64>>,
+}
+
+impl Query {
+    pub fn matches(&self, map: &Map) -> bool {
+        let words: Vec<String> = self.text.split_whitespace().map(str::to_lowercase).collect();
+        if !words.is_empty() {
+            let metadata = &map.metadata;
+            let searched = [
+                &metadata.title,
+                &metadata.title_unicode,
+                &metadata.artist,
+                &metadata.artist_unicode,
+                &metadata.creator,
+                &metadata.version,
+            ].into_iter()
+                .chain(metadata.tags.iter())
+                .map(|field| field.to_lowercase())
+                .collect::<Vec<_>>()
+                .join("\n");
+            if !words.iter().all(|word| searched.contains(word.as_str())) {
+                return false;
+            }
+        }
+        if self.column_count.as_ref().is_some_and(|range| !range.contains(&map.column_count)) {
+            return false;
+        }
+        // Cheapest first, the difficulty goes over the whole map
+        if self.length.is_some() || self.note_count.is_some() {
+            let statistics = map.statistics();
+            if self.length.as_ref().is_some_and(|range| !range.contains(&statistics.length))
+                || self.note_count.as_ref().is_some_and(|range| !range.contains(&statistics.note_count))
+            {
+                return false;
+            }
+        }
+        if let Some(range) = &self.bpm {
+            if !map.main_bpm().is_some_and(|bpm| range.contains(&bpm)) {
+                return false;
+            }
+        }
+        if let Some(range) = &self.stars {
+            if !range.contains(&map.difficulty().stars) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+impl FromStr for Query {
+    type Err = ParseError;
+
+    /// Reads words to search for, mixed with `key:range` filters. Keys are
+    /// `keys`, `length` (in seconds), `notes`, `bpm` and `stars`, and ranges
+    /// are `min-max`, `min-`, `-max` or a single value, like
+    /// `camellia keys:7 stars:3-5 length:-120`. Words with other keys, like
+    /// `re:zero`, are searched for as they are.
+    fn from_str(text: &str) -> Result<Query, ParseError> {
+        let mut query = Query::default();
+        let mut words = Vec::new();
+        for word in text.split_whitespace() {
+            let Some((key, value)) = word.split_once(':') else {
+                words.push(word);
+                continue;
+            };
+            match key.to_ascii_lowercase().as_str() {
+                "keys" => query.column_count = Some(parse_range("keys", value, 0, usize::MAX)?),
+                "length" => {
+                    let seconds = parse_range("length", value, 0, usize::MAX/1000)?;
+                    query.length = Some(seconds.start()*1000..=seconds.end()*1000);
+                },
+                "notes" => query.note_count = Some(parse_range("notes", value, 0, usize::MAX)?),
+                "bpm" => query.bpm = Some(parse_range("bpm", value, 0.0, f64::INFINITY)?),
+                "stars" => query.stars = Some(parse_range("stars", value, 0.0, f64::INFINITY)?),
+                _ => words.push(word),
+            }
+        }
+        query.text = words.join(" ");
+        Ok(query)
+    }
+}
+
+/// Reads `min-max`, `min-`, `-max` or a single value, with `min` and `max`
+/// for the ends left out.
+fn parse_range<T>(field: &'static str, value: &str, min: T, max: T) -> Result<RangeInclusive<T>, ParseError> where
+    T: FromStr + Copy
+{
+    let (start, end) = match value.split_once('-') {
+        Some((start, end)) => (start, end),
+        None => (value, value),
+    };
+    let start = if start.is_empty() { min } else { parse_number(field, start)? };
+    let end = if end.is_empty() { max } else { parse_number(field, end)? };
+    Ok(start..=end)
+}
+
+/// Maps in `sets` that match `query`, as `(set, map)` indices in the order
+/// they're in `sets`.
+pub fn search(sets: &[Set], query: &Query) -> Vec<(usize, usize)> {
+    sets.iter()
+        .enumerate()
+        .flat_map(|(set_index, set)| set.maps.iter()
+            .enumerate()
+            .filter(|(_, map)| query.matches(map))
+            .map(move |(map_index, _)| (set_index, map_index)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 4K map at 120 BPM with a note every beat from 1s to 10s.
+    fn map(title: &str, version: &str, tags: &str) -> Map {
+        let mut osu = format!("osu file format v14
+[General]
+AudioFilename: audio.mp3
+Mode: 3
+[Metadata]
+Title:{}
+Artist:Artist
+Creator:Mapper
+Version:{}
+Tags:{}
+[Difficulty]
+CircleSize:4
+[TimingPoints]
+0,500,4,2,0,60,1,0
+[HitObjects]
+", title, version, tags);
+        for (index, time) in (1000..=10_000).step_by(500).enumerate() {
+            osu.push_str(&format!("{},192,{},1,0,0:0:0:0:\n", 64 + (index % 4)*128, time));
+        }
+        Map::from_osu(osu.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn parses_words_and_filters() {
+        let query: Query = "Freedom  keys:4-7 Dive length:60- notes:-500 bpm:180 stars:2.5-4 re:zero".parse().unwrap();
+        assert_eq!(query, Query {
+            text: "Freedom Dive re:zero".to_string(),
+            column_count: Some(4..=7),
+            length: Some(60_000..=usize::MAX/1000*1000),
+            note_count: Some(0..=500),
+            bpm: Some(180.0..=180.0),
+            stars: Some(2.5..=4.0),
+        });
+        assert_eq!("".parse::<Query>().unwrap(), Query::default());
+        assert!(matches!("stars:two".parse::<Query>(), Err(ParseError::InvalidNumber { field: "stars", .. })));
+    }
+
+    #[test]
+    fn matches_every_word_in_any_field() {
+        let map = map("Freedom Dive", "FOUR DIMENSIONS", "xi touhou");
+        for (text, matches) in [
+            ("", true),
+            ("freedom", true),
+            ("dive four", true),
+            ("TOUHOU", true),
+            ("mapper artist", true),
+            ("freedom blue", false),
+        ] {
+            let query = Query {
+                text: text.to_string(),
+                ..Query::default()
+            };
+            assert_eq!(query.matches(&map), matches, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn filters_narrow_down_maps() {
+        let map = map("Song", "Hard", "");
+        for (text, matches) in [
+            ("keys:4", true),
+            ("keys:7", false),
+            ("notes:19", true),
+            ("notes:20-", false),
+            ("length:9-10", true),
+            ("length:-8", false),
+            ("bpm:120", true),
+            ("bpm:150-", false),
+            ("stars:0-", true),
+            ("stars:-0", false),
+            ("song keys:4 bpm:100-130", true),
+        ] {
+            assert_eq!(text.parse::<Query>().unwrap().matches(&map), matches, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn search_finds_maps_in_set_order() {
+        let sets: Vec<Set> = ["a", "b"].iter()
+            .map(|name| {
+                let mut written = Vec::new();
+                let mut zip = zip::ZipWriter::new(std::io::Cursor::new(&mut written));
+                for version in ["Easy", "Hard"] {
+                    zip.start_file(format!("{} ({}).osu", name, version), Default::default()).unwrap();
+                    map(name, version, "").write_osu(&mut zip).unwrap();
+                }
+                zip.finish().unwrap();
+                drop(zip);
+                Set::from_osz(std::io::Cursor::new(written)).unwrap()
+            })
+            .collect();
+        assert_eq!(search(&sets, &"hard".parse().unwrap()), [(0, 1), (1, 1)]);
+        assert_eq!(search(&sets, &Query::default()).len(), 4);
+    }
+}
@@ -0,0 +1,180 @@
+use crate::{
+    Map,
+    Note,
+};
+
+/// Notes closer than a 1/4 beat (plus this, for rounding) keep a stream going.
+const STREAM_TOLERANCE: f64 = 5.0;
+/// Gap that counts as a 1/4 beat in maps without timing points.
+const DEFAULT_STREAM_GAP: f64 = 150.0;
+
+/// Counts for a whole map, see [`Map::statistics`]. For how notes are spread
+/// over the map, see [`Map::density`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Statistics {
+    pub note_count: usize,
+    pub hold_count: usize,
+    /// Milliseconds from the first note to the end of the last.
+    pub length: usize,
+    /// Notes per second over `length`.
+    pub average_nps: f64,
+    /// Most notes that start within any one second.
+    pub peak_nps: usize,
+    /// Number of rows (notes at the same time) by their size, so
+    /// `chord_sizes[2]` is the number of two note chords. `column_count + 1`
+    /// long.
+    pub chord_sizes: Vec<usize>,
+    /// Notes in each column that come right after a note in the same column,
+    /// with no other row between them.
+    pub jacks: Vec<usize>,
+    /// Most notes in a stream: two or more rows in a row, each at most a 1/4
+    /// beat after the last and without jacks. 0 when there are none.
+    pub longest_stream: usize,
+}
+
+impl Map {
+    pub fn statistics(&self) -> Statistics {
+        let rows = self.note_rows();
+        let note_count = rows.iter().map(Vec::len).sum();
+        let hold_count = self.notes.iter().flatten().filter(|note| note.is_hold()).count();
+        let start = rows.first().map_or(0, |row| row[0].1.time);
+        let end = self.notes.iter()
+            .flatten()
+            .map(|note| note.end_time.unwrap_or(note.time))
+            .max()
+            .unwrap_or(0);
+        let length = end - start;
+        let average_nps = if length == 0 {
+            0.0
+        } else {
+            note_count as f64/(length as f64/1000.0)
+        };
+
+        let times: Vec<usize> = rows.iter()
+            .flat_map(|row| row.iter().map(|(_, note)| note.time))
+            .collect();
+        let mut peak_nps = 0;
+        let mut window_start = 0;
+        for (index, time) in times.iter().enumerate() {
+            while times[window_start] + 1000 <= *time {
+                window_start += 1;
+            }
+            peak_nps = peak_nps.max(index + 1 - window_start);
+        }
+
+        let mut chord_sizes = vec![0; self.column_count + 1];
+        let mut jacks = vec![0; self.column_count];
+        let mut longest_stream = 0;
+        let mut stream = 0;
+        for (index, row) in rows.iter().enumerate() {
+            // Stacked notes in one column can make a row wider than the map
+            chord_sizes[row.len().min(self.column_count)] += 1;
+            let previous = index.checked_sub(1).map(|previous| &rows[previous]);
+            let mut jack = false;
+            for (column, _) in row.iter() {
+                if previous.is_some_and(|previous| previous.iter().any(|(previous_column, _)| previous_column == column)) {
+                    jacks[*column] += 1;
+                    jack = true;
+                }
+            }
+            let time = row[0].1.time;
+            let streaming = previous.is_some_and(|previous| {
+                let gap = self.tempo_at(time as f64).map_or(DEFAULT_STREAM_GAP, |tempo| tempo.beat_length/4.0);
+                ((time - previous[0].1.time) as f64) <= gap + STREAM_TOLERANCE
+            });
+            stream = match previous {
+                // A stream needs at least two rows, so it starts with both
+                Some(previous) if streaming && !jack && stream == 0 => previous.len() + row.len(),
+                _ if streaming && !jack => stream + row.len(),
+                _ => 0,
+            };
+            longest_stream = longest_stream.max(stream);
+        }
+
+        Statistics {
+            note_count,
+            hold_count,
+            length,
+            average_nps,
+            peak_nps,
+            chord_sizes,
+            jacks,
+            longest_stream,
+        }
+    }
+
+    /// Notes starting in each of `bucket_count` equal parts of the map, from
+    /// the first note to the last, for drawing a density graph.
+    pub fn density(&self, bucket_count: usize) -> Vec<usize> {
+        let mut buckets = vec![0; bucket_count];
+        let times = || self.notes.iter().flatten().map(|note| note.time);
+        let (Some(start), Some(end)) = (times().min(), times().max()) else {
+            return buckets;
+        };
+        if bucket_count == 0 {
+            return buckets;
+        }
+        for time in times() {
+            let bucket = (time - start)*bucket_count/(end - start + 1);
+            buckets[bucket] += 1;
+        }
+        buckets
+    }
+
+    /// Every note as `(column, note)`, grouped by start time.
+    pub(crate) fn note_rows(&self) -> Vec<Vec<(usize, Note)>> {
+        let mut notes: Vec<(usize, Note)> = self.notes.iter()
+            .enumerate()
+            .flat_map(|(column, notes)| notes.iter().map(move |note| (column, *note)))
+            .collect();
+        notes.sort_by_key(|(column, note)| (note.time, *column));
+        let mut rows: Vec<Vec<(usize, Note)>> = Vec::new();
+        for (column, note) in notes {
+            match rows.last_mut() {
+                Some(row) if row[0].1.time == note.time => row.push((column, note)),
+                _ => rows.push(vec![(column, note)]),
+            }
+        }
+        rows
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(notes: Vec<Vec<usize>>) -> Map {
+        let mut map = Map::from_osu("osu file format v14
+[General]
+AudioFilename: audio.mp3
+Mode: 3
+[Metadata]
+Title:Song
+Version:Hard
+[Difficulty]
+CircleSize:4
+[TimingPoints]
+0,500,4,2,0,60,1,0
+".as_bytes()).unwrap();
+        map.notes = notes.into_iter()
+            .map(|times| times.into_iter().map(|time| Note {
+                time,
+                end_time: None,
+            }).collect())
+            .collect();
+        map
+    }
+
+    #[test]
+    fn lone_rows_are_not_streams() {
+        let map = map(vec![vec![500], vec![1000], vec![1000], vec![2000]]);
+        assert_eq!(map.statistics().longest_stream, 0);
+    }
+
+    #[test]
+    fn streams_count_every_note() {
+        // 1/4 beats at 120 BPM are 125ms, the jack at 1375 ends the first
+        let map = map(vec![vec![1000], vec![1125, 1500], vec![1250, 1375], vec![1250, 5000]]);
+        assert_eq!(map.statistics().longest_stream, 4);
+    }
+}
@@ -0,0 +1,412 @@
+use std::{
+    io::Read,
+    sync::OnceLock,
+};
+
+use crate::{
+    DEFAULT_DIFFICULTY,
+    Diagnostic,
+    full_title,
+    Map,
+    Metadata,
+    non_empty,
+    Note,
+    ParseError,
+    parse_finite,
+    sibling_file_name,
+    Warning,
+    timing::BeatTiming,
+};
+
+/// StepMania's steps types that fit in [`crate::MAX_COLUMN_COUNT`] columns.
+const STEPS_TYPES: [(&str, usize); 9] = [
+    ("dance-single", 4),
+    ("dance-double", 8),
+    ("dance-couple", 8),
+    ("dance-solo", 6),
+    ("dance-threepanel", 3),
+    ("pump-single", 5),
+    ("pump-halfdouble", 6),
+    ("pump-double", 10),
+    ("kb7-single", 7),
+];
+
+/// A `#NAME:value;` pair.
+struct Tag {
+    line: usize,
+    name: String,
+    value: String,
+}
+
+#[derive(Default)]
+struct Chart {
+    line: usize,
+    steps_type: String,
+    difficulty: String,
+    meter: String,
+    credit: String,
+    notes: String,
+    // .ssc charts can have their own timing
+    offset: Option<f64>,
+    bpms: Option<Vec<(f64, f64)>>,
+    stops: Option<Vec<(f64, f64)>>,
+    delays: Option<Vec<(f64, f64)>>,
+}
+
+/// Parses a .sm or .ssc file into a map per supported chart. `file_name` is
+/// needed to find the music next to it.
+pub(crate) fn read<R>(file_name: &str, mut reader: R, warnings: &mut Vec<Diagnostic<Warning>>) -> Result<Vec<Map>, Diagnostic<ParseError>> where
+    R: Read
+{
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes).map_err(|error| Diagnostic::at(None, error.into()))?;
+    // Older simfiles aren't always UTF-8
+    let text = String::from_utf8_lossy(&bytes);
+
+    let mut title = None;
+    let mut subtitle = None;
+    let mut title_translit = None;
+    let mut artist = None;
+    let mut artist_translit = None;
+    let mut credit = String::new();
+    let mut genre = String::new();
+    let mut music = None;
+    let mut timing = BeatTiming::default();
+    let mut charts: Vec<Chart> = Vec::new();
+    // Only set between an .ssc #NOTEDATA and its #NOTES
+    let mut ssc_chart: Option<Chart> = None;
+
+    for tag in read_tags(&text) {
+        let line = Some(tag.line);
+        let value = tag.value.trim();
+        match (&mut ssc_chart, tag.name.as_str()) {
+            (_, "NOTEDATA") => {
+                ssc_chart = Some(Chart {
+                    line: tag.line,
+                    ..Chart::default()
+                });
+            },
+            (Some(chart), "STEPSTYPE") => chart.steps_type = value.to_string(),
+            (Some(chart), "DIFFICULTY") => chart.difficulty = value.to_string(),
+            (Some(chart), "METER") => chart.meter = value.to_string(),
+            (Some(chart), "CREDIT") => chart.credit = value.to_string(),
+            (Some(chart), "OFFSET") => chart.offset = Some(parse_finite("OFFSET", value).map_err(|error| Diagnostic::at(line, error))?),
+            (Some(chart), "BPMS") => chart.bpms = Some(parse_pairs("BPMS", value).map_err(|error| Diagnostic::at(line, error))?),
+            (Some(chart), "STOPS") => chart.stops = Some(parse_pairs("STOPS", value).map_err(|error| Diagnostic::at(line, error))?),
+            (Some(chart), "DELAYS") => chart.delays = Some(parse_pairs("DELAYS", value).map_err(|error| Diagnostic::at(line, error))?),
+            (Some(chart), "NOTES") => {
+                chart.notes = value.to_string();
+                charts.extend(ssc_chart.take());
+            },
+            (None, "NOTES") => {
+                // .sm charts are "type:author:difficulty:meter:radar values:notes"
+                let fields: Vec<&str> = value.splitn(6, ':').map(str::trim).collect();
+                if fields.len() != 6 {
+                    warnings.push(Diagnostic::at(line, Warning::IncompleteChart));
+                    continue;
+                }
+                charts.push(Chart {
+                    line: tag.line,
+                    steps_type: fields[0].to_string(),
+                    credit: fields[1].to_string(),
+                    difficulty: fields[2].to_string(),
+                    meter: fields[3].to_string(),
+                    notes: fields[5].to_string(),
+                    ..Chart::default()
+                });
+            },
+            (_, "TITLE") => title = non_empty(value),
+            (_, "SUBTITLE") => subtitle = non_empty(value),
+            (_, "TITLETRANSLIT") => title_translit = non_empty(value),
+            (_, "ARTIST") => artist = non_empty(value),
+            (_, "ARTISTTRANSLIT") => artist_translit = non_empty(value),
+            (_, "CREDIT") => credit = value.to_string(),
+            (_, "GENRE") => genre = value.to_string(),
+            (_, "MUSIC") => music = non_empty(value),
+            (_, "OFFSET") => timing.offset = parse_finite("OFFSET", value).map_err(|error| Diagnostic::at(line, error))?,
+            (_, "BPMS") => timing.bpms = parse_pairs("BPMS", value).map_err(|error| Diagnostic::at(line, error))?,
+            (_, "STOPS") | (_, "FREEZES") => timing.stops = parse_pairs("STOPS", value).map_err(|error| Diagnostic::at(line, error))?,
+            (_, "DELAYS") => timing.delays = parse_pairs("DELAYS", value).map_err(|error| Diagnostic::at(line, error))?,
+            // Banners, previews, scroll gimmicks, ...
+            _ => {},
+        }
+    }
+
+    let music = music.ok_or_else(|| Diagnostic::at(None, ParseError::MissingAudioFile))?;
+    let title = title.or(title_translit.clone()).ok_or_else(|| Diagnostic::at(None, ParseError::MissingTitle))?;
+    let title = match subtitle {
+        Some(subtitle) => format!("{} {}", title, subtitle),
+        None => title,
+    };
+    let artist = artist.or(artist_translit.clone()).unwrap_or_default();
+    let audio_file_name = sibling_file_name(file_name, &music);
+
+    let mut maps = Vec::new();
+    for chart in charts {
+        let line = Some(chart.line);
+        let column_count = match STEPS_TYPES.iter().find(|(steps_type, _)| *steps_type == chart.steps_type) {
+            Some((_, column_count)) => *column_count,
+            None => {
+                warnings.push(Diagnostic::at(line, Warning::UnsupportedChartType(chart.steps_type)));
+                continue;
+            }
+        };
+
+        let mut timing = BeatTiming {
+            // StepMania's offset is when the music starts relative to beat 0
+            offset: -chart.offset.unwrap_or(timing.offset)*1000.0,
+            bpms: chart.bpms.unwrap_or_else(|| timing.bpms.clone()),
+            stops: chart.stops.unwrap_or_else(|| timing.stops.clone()),
+            delays: chart.delays.unwrap_or_else(|| timing.delays.clone()),
+        };
+        // Negative BPMs are used for warps, which aren't supported
+        if timing.bpms.is_empty() || timing.bpms.iter().any(|(_, bpm)| *bpm <= 0.0) {
+            warnings.push(Diagnostic::at(line, Warning::UnsupportedBpms(format!("{:?}", timing.bpms))));
+            continue;
+        }
+        timing.bpms.sort_by(|a, b| a.0.total_cmp(&b.0));
+        for (_, length) in timing.stops.iter_mut().chain(timing.delays.iter_mut()) {
+            *length *= 1000.0;
+        }
+
+        let notes = read_notes(&chart.notes, column_count, &timing, line, warnings);
+        let version = if chart.meter.is_empty() {
+            chart.difficulty
+        } else {
+            format!("{} {}", chart.difficulty, chart.meter)
+        };
+        let version = if chart.steps_type == STEPS_TYPES[0].0 {
+            version
+        } else {
+            format!("{} {}", chart.steps_type, version)
+        };
+
+        maps.push(Map {
+            format_version: 0,
+            audio_file_name: audio_file_name.clone(),
+            audio_lead_in: 0,
+            full_title: full_title(&version, &title),
+            metadata: Metadata {
+                title: title_translit.clone().unwrap_or_else(|| title.clone()),
+                title_unicode: title.clone(),
+                artist: artist_translit.clone().unwrap_or_else(|| artist.clone()),
+                artist_unicode: artist.clone(),
+                creator: if chart.credit.is_empty() { credit.clone() } else { chart.credit },
+                version,
+                source: String::new(),
+                tags: genre.split_whitespace().map(str::to_string).collect(),
+                beatmap_id: None,
+                beatmap_set_id: None,
+            },
+            column_count,
+            hp_drain_rate: DEFAULT_DIFFICULTY,
+            overall_difficulty: DEFAULT_DIFFICULTY,
+            notes,
+            timing_points: timing.timing_points(4),
+            samples: Vec::new(),
+            checksum: String::new(),
+            audio_checksum: OnceLock::new(),
+        });
+    }
+
+    if maps.is_empty() {
+        return Err(Diagnostic::at(None, ParseError::NoSupportedCharts));
+    }
+    Ok(maps)
+}
+
+/// Reads measures of note rows. Each measure is 4 beats split evenly between
+/// its rows.
+fn read_notes(data: &str, column_count: usize, timing: &BeatTiming, line: Option<usize>, warnings: &mut Vec<Diagnostic<Warning>>) -> Vec<Vec<Note>> {
+    let mut warn = |reason| warnings.push(Diagnostic::at(line, reason));
+    let mut notes = vec![Vec::new(); column_count];
+    // Start times of holds waiting for their tail
+    let mut holds: Vec<Option<f64>> = vec![None; column_count];
+    for (measure, rows) in data.split(',').enumerate() {
+        let rows: Vec<&str> = rows.lines().map(str::trim).filter(|row| !row.is_empty()).collect();
+        for (index, row) in rows.iter().enumerate() {
+            let beat = (measure*4) as f64 + (index*4) as f64/rows.len() as f64;
+            let time = timing.time_at(beat);
+            if row.len() < column_count {
+                warn(Warning::IncompleteRow);
+                continue;
+            }
+            for (column, kind) in row.chars().take(column_count).enumerate() {
+                match kind {
+                    // Tap and lift
+                    '1' | 'L' => match to_time(time) {
+                        Some(time) => notes[column].push(Note {
+                            time,
+                            end_time: None,
+                        }),
+                        None => warn(Warning::NoteBeforeAudio),
+                    },
+                    // Hold and roll heads
+                    '2' | '4' => holds[column] = Some(time),
+                    '3' => match holds[column].take() {
+                        Some(start) => match (to_time(start), to_time(time)) {
+                            (Some(start), Some(end)) => notes[column].push(Note {
+                                time: start,
+                                end_time: Some(end),
+                            }),
+                            _ => warn(Warning::NoteBeforeAudio),
+                        },
+                        None => warn(Warning::UnmatchedHoldTail),
+                    },
+                    // Empty, mines, keysounds and fakes
+                    _ => {},
+                }
+            }
+        }
+    }
+    if holds.iter().any(Option::is_some) {
+        warn(Warning::UnterminatedHold);
+    }
+    notes
+}
+
+/// Splits a simfile into its tags, dropping comments.
+fn read_tags(text: &str) -> Vec<Tag> {
+    let mut tags = Vec::new();
+    let mut current: Option<Tag> = None;
+    for (index, line) in text.lines().enumerate() {
+        let mut rest = match line.find("//") {
+            Some(comment) => &line[..comment],
+            None => line,
+        };
+        // StepMania ends a tag missing its ; when a new one starts a line
+        if rest.trim_start().starts_with('#') {
+            tags.extend(current.take());
+        }
+        loop {
+            match &mut current {
+                Some(tag) => match rest.find(';') {
+                    Some(end) => {
+                        tag.value.push_str(&rest[..end]);
+                        tags.extend(current.take());
+                        rest = &rest[end+1..];
+                    },
+                    None => {
+                        tag.value.push_str(rest);
+                        tag.value.push('\n');
+                        break;
+                    }
+                },
+                None => match rest.find('#') {
+                    Some(start) => {
+                        let (name, value) = rest[start+1..].split_once(':').unwrap_or((&rest[start+1..], ""));
+                        current = Some(Tag {
+                            line: index+1,
+                            name: name.trim().to_ascii_uppercase(),
+                            value: String::new(),
+                        });
+                        rest = value;
+                    },
+                    None => break,
+                }
+            }
+        }
+    }
+    tags.extend(current);
+    tags
+}
+
+fn to_time(time: f64) -> Option<usize> {
+    if time >= 0.0 {
+        Some(time.round() as usize)
+    } else {
+        None
+    }
+}
+
+/// Reads `beat=value,beat=value` lists.
+fn parse_pairs(field: &'static str, value: &str) -> Result<Vec<(f64, f64)>, ParseError> {
+    value.split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((beat, value)) => Ok((parse_finite(field, beat)?, parse_finite(field, value)?)),
+            None => Err(ParseError::InvalidNumber {
+                field,
+                value: pair.to_string(),
+            }),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FileReport;
+
+    /// Music starts 100ms before beat 0, 120 BPM up to beat 4 and 240 BPM
+    /// after, a 500ms stop on beat 2 and a 250ms delay on beat 5.
+    const SIMFILE: &str = "#TITLE:Song;
+#ARTIST:Artist;
+#MUSIC:song.ogg;
+#OFFSET:-0.1;
+#BPMS:0=120,4=240;
+#STOPS:2=0.5;
+#DELAYS:5=0.25;
+#NOTES:
+     dance-single:
+     Mapper:
+     Hard:
+     9:
+     0,0,0,0,0:
+1000
+0200
+0010
+0300
+,
+0001
+4000
+0000
+3000
+;
+";
+
+    fn parse(bytes: &[u8]) -> Map {
+        let (maps, report) = FileReport::parse("Song/song.sm", bytes, read);
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+        maps.unwrap().remove(0)
+    }
+
+    #[test]
+    fn offsets_bpm_changes_and_pauses_move_notes() {
+        let map = parse(SIMFILE.as_bytes());
+        assert_eq!(map.audio_file_name, "Song/song.ogg");
+        assert_eq!(map.full_title, "Hard 9 - Song");
+        assert_eq!(map.column_count, 4);
+        // Beat 0 lands on the offset
+        assert_eq!(map.notes[0][0].time, 100);
+        // The stop comes after the notes on its own beat
+        assert_eq!(map.notes[2][0].time, 1100);
+        // 4 beats at 120 BPM and the stop
+        assert_eq!(map.notes[3][0].time, 2600);
+    }
+
+    #[test]
+    fn hold_and_roll_tails_end_their_heads() {
+        let map = parse(SIMFILE.as_bytes());
+        assert_eq!(map.notes[1], vec![Note {
+            time: 600,
+            end_time: Some(2100),
+        }]);
+        // The roll starts after the delay on its beat, and a beat at 240 BPM
+        // is 250ms
+        assert_eq!(map.notes[0][1], Note {
+            time: 3100,
+            end_time: Some(3600),
+        });
+    }
+
+    #[test]
+    fn unmatched_tails_and_unterminated_heads_warn() {
+        let simfile = SIMFILE.replace("0300", "0000").replace("3000", "0030");
+        let (maps, report) = FileReport::parse("song.sm", simfile.as_bytes(), read);
+        assert!(maps.unwrap()[0].notes[1].is_empty());
+        let warnings: Vec<&Warning> = report.warnings.iter().map(|warning| &warning.reason).collect();
+        assert_eq!(warnings, [&Warning::UnmatchedHoldTail, &Warning::UnterminatedHold]);
+    }
+}
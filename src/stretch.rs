@@ -0,0 +1,127 @@
+use std::{
+    collections::VecDeque,
+    time::Duration,
+};
+
+use rodio::Source;
+
+/// Frames in each window that gets moved.
+const WINDOW_LENGTH: usize = 2048;
+/// Windows overlap by half, where a Hann window adds back up to 1.
+const HOP_LENGTH: usize = WINDOW_LENGTH/2;
+
+/// Plays `source` at `rate` times speed without changing its pitch, by
+/// overlapping windows of it taken further apart (or closer together) than
+/// they are played. Plain overlap-add, so it smears a little.
+pub struct TimeStretch<S> where
+    S: Source<Item = f32>
+{
+    source: S,
+    channels: usize,
+    sample_rate: u32,
+    rate: f64,
+    window: Vec<f32>,
+    /// Interleaved frames read from `source`, starting at frame `input_start`.
+    input: Vec<f32>,
+    input_start: usize,
+    /// Frame where the next window is taken from.
+    position: f64,
+    /// Second half of the last window, added to the first half of the next.
+    overlap: Vec<f32>,
+    output: VecDeque<f32>,
+    source_done: bool,
+}
+
+impl<S> TimeStretch<S> where
+    S: Source<Item = f32>
+{
+    pub fn new(source: S, rate: f64) -> TimeStretch<S> {
+        let channels = usize::from(source.channels());
+        let sample_rate = source.sample_rate();
+        let window = (0..WINDOW_LENGTH)
+            .map(|frame| (0.5 - 0.5*(2.0*std::f64::consts::PI*frame as f64/WINDOW_LENGTH as f64).cos()) as f32)
+            .collect();
+        TimeStretch {
+            source,
+            channels,
+            sample_rate,
+            rate,
+            window,
+            input: Vec::new(),
+            input_start: 0,
+            position: 0.0,
+            overlap: vec![0.0; HOP_LENGTH*channels],
+            output: VecDeque::new(),
+            source_done: false,
+        }
+    }
+
+    /// Adds the next window to `output`. False once `source` is used up.
+    fn stretch_window(&mut self) -> bool {
+        let start = self.position as usize;
+        let needed = (start + WINDOW_LENGTH - self.input_start)*self.channels;
+        while self.input.len() < needed && !self.source_done {
+            match self.source.next() {
+                Some(sample) => self.input.push(sample),
+                None => self.source_done = true,
+            }
+        }
+        let offset = (start - self.input_start)*self.channels;
+        if self.source_done && offset >= self.input.len() {
+            self.output.extend(self.overlap.drain(..));
+            return false;
+        }
+
+        let mut segment = vec![0.0; WINDOW_LENGTH*self.channels];
+        for (index, sample) in segment.iter_mut().enumerate() {
+            // Past the end of the source is silence
+            if let Some(input) = self.input.get(offset + index) {
+                *sample = input*self.window[index/self.channels];
+            }
+        }
+        let (first, second) = segment.split_at(HOP_LENGTH*self.channels);
+        self.output.extend(first.iter().zip(self.overlap.iter()).map(|(a, b)| a + b));
+        self.overlap = second.to_vec();
+
+        self.position += HOP_LENGTH as f64*self.rate;
+        let drop = (self.position as usize - self.input_start).min(self.input.len()/self.channels);
+        self.input.drain(..drop*self.channels);
+        self.input_start += drop;
+        true
+    }
+}
+
+impl<S> Iterator for TimeStretch<S> where
+    S: Source<Item = f32>
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        while self.output.is_empty() {
+            if !self.stretch_window() && self.output.is_empty() {
+                return None;
+            }
+        }
+        self.output.pop_front()
+    }
+}
+
+impl<S> Source for TimeStretch<S> where
+    S: Source<Item = f32>
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels as u16
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
@@ -0,0 +1,209 @@
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::Map;
+
+/// A line in the [TimingPoints] section.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TimingPoint {
+    /// Start time in milliseconds.
+    pub time: f64,
+    /// For uninherited points, milliseconds per beat. For inherited points,
+    /// a negative inverse slider velocity percentage (-50 is 2x).
+    pub beat_length: f64,
+    /// Beats in a measure.
+    pub meter: u32,
+    /// Uninherited points start a new tempo, inherited points only change the
+    /// slider velocity.
+    pub uninherited: bool,
+    pub kiai: bool,
+}
+
+impl TimingPoint {
+    /// Beats per minute, only for uninherited points.
+    pub fn bpm(&self) -> Option<f64> {
+        if self.uninherited {
+            Some(60_000.0/self.beat_length)
+        } else {
+            None
+        }
+    }
+
+    /// Scroll speed multiplier. Always 1 for uninherited points.
+    pub fn slider_velocity(&self) -> f64 {
+        if self.uninherited {
+            1.0
+        } else {
+            // Same limits osu! uses
+            (-100.0/self.beat_length).clamp(0.1, 10.0)
+        }
+    }
+}
+
+impl Map {
+    /// The uninherited timing point in effect at `time`. Times before the
+    /// first timing point use the first one.
+    pub fn tempo_at(&self, time: f64) -> Option<&TimingPoint> {
+        let mut tempos = self.timing_points.iter().filter(|point| point.uninherited);
+        let first = tempos.next()?;
+        Some(tempos.take_while(|point| point.time <= time).last().unwrap_or(first))
+    }
+
+    pub fn bpm_at(&self, time: f64) -> Option<f64> {
+        self.tempo_at(time).and_then(TimingPoint::bpm)
+    }
+
+    /// The BPM the map spends the longest at between its first note and the
+    /// end of its last, like the one osu! shows in song select.
+    pub fn main_bpm(&self) -> Option<f64> {
+        let notes = || self.notes.iter().flatten();
+        let start = notes().map(|note| note.time).min().unwrap_or(0) as f64;
+        let end = notes().map(|note| note.end_time.unwrap_or(note.time)).max().unwrap_or(0) as f64;
+        let tempos: Vec<&TimingPoint> = self.timing_points.iter().filter(|point| point.uninherited).collect();
+        // (beat length, milliseconds at it)
+        let mut durations: Vec<(f64, f64)> = Vec::new();
+        for (index, tempo) in tempos.iter().enumerate() {
+            // The first tempo also covers notes before it
+            let from = if index == 0 { start } else { tempo.time.max(start) };
+            let to = tempos.get(index + 1).map_or(end, |next| next.time.min(end));
+            let duration = (to - from).max(0.0);
+            match durations.iter_mut().find(|(beat_length, _)| *beat_length == tempo.beat_length) {
+                Some((_, total)) => *total += duration,
+                None => durations.push((tempo.beat_length, duration)),
+            }
+        }
+        // Reversed so the earliest tempo wins ties
+        durations.iter()
+            .rev()
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(beat_length, _)| 60_000.0/beat_length)
+    }
+
+    /// The slider velocity in effect at `time`. Inherited points only last
+    /// until the next uninherited point.
+    pub fn slider_velocity_at(&self, time: f64) -> f64 {
+        self.timing_points.iter()
+            .take_while(|point| point.time <= time)
+            .last()
+            .map_or(1.0, TimingPoint::slider_velocity)
+    }
+
+    pub fn kiai_at(&self, time: f64) -> bool {
+        self.timing_points.iter()
+            .take_while(|point| point.time <= time)
+            .last()
+            .is_some_and(|point| point.kiai)
+    }
+
+    /// Number of beats between the first uninherited timing point and `time`,
+    /// following every tempo change. Negative before the first point.
+    pub fn beat_at(&self, time: f64) -> Option<f64> {
+        let mut tempos = self.timing_points.iter().filter(|point| point.uninherited).peekable();
+        let mut current = tempos.next()?;
+        let mut beats = 0.0;
+        while let Some(next) = tempos.peek() {
+            if next.time > time {
+                break;
+            }
+            beats += (next.time-current.time)/current.beat_length;
+            current = tempos.next().unwrap();
+        }
+        Some(beats + (time-current.time)/current.beat_length)
+    }
+
+    /// Inverse of [`Map::beat_at`].
+    pub fn time_at_beat(&self, beat: f64) -> Option<f64> {
+        let mut tempos = self.timing_points.iter().filter(|point| point.uninherited).peekable();
+        let mut current = tempos.next()?;
+        let mut beats = 0.0;
+        while let Some(next) = tempos.peek() {
+            let section_beats = (next.time-current.time)/current.beat_length;
+            if beats + section_beats > beat {
+                break;
+            }
+            beats += section_beats;
+            current = tempos.next().unwrap();
+        }
+        Some(current.time + (beat-beats)*current.beat_length)
+    }
+}
+
+/// Converts beat positions to milliseconds for formats that place notes on
+/// beats (StepMania, BMS, Malody).
+#[derive(Debug, Clone, Default)]
+pub(crate) struct BeatTiming {
+    /// Time of beat 0 in milliseconds.
+    pub offset: f64,
+    /// `(beat, bpm)` pairs sorted by beat.
+    pub bpms: Vec<(f64, f64)>,
+    /// `(beat, milliseconds)` pauses that happen after the notes on that beat.
+    pub stops: Vec<(f64, f64)>,
+    /// `(beat, milliseconds)` pauses that happen before the notes on that beat.
+    pub delays: Vec<(f64, f64)>,
+}
+
+impl BeatTiming {
+    /// Milliseconds at `beat`. Needs at least one bpm.
+    pub fn time_at(&self, beat: f64) -> f64 {
+        let mut time = self.offset;
+        let mut current_beat = 0.0;
+        let mut bpm = self.bpms[0].1;
+        for (change_beat, next_bpm) in self.bpms.iter().copied() {
+            if change_beat <= 0.0 {
+                bpm = next_bpm;
+                continue;
+            }
+            if change_beat >= beat {
+                break;
+            }
+            time += (change_beat-current_beat)*60_000.0/bpm;
+            current_beat = change_beat;
+            bpm = next_bpm;
+        }
+        time += (beat-current_beat)*60_000.0/bpm;
+        time += self.stops.iter().filter(|(stop_beat, _)| *stop_beat < beat).map(|(_, length)| length).sum::<f64>();
+        time += self.delays.iter().filter(|(delay_beat, _)| *delay_beat <= beat).map(|(_, length)| length).sum::<f64>();
+        time
+    }
+
+    pub fn bpm_at(&self, beat: f64) -> f64 {
+        self.bpms.iter()
+            .take_while(|(change_beat, _)| *change_beat <= beat)
+            .last()
+            .unwrap_or(&self.bpms[0])
+            .1
+    }
+
+    /// Uninherited timing points for every tempo change. Pauses become a
+    /// timing point where they end, so beats line up again afterwards, but
+    /// [`Map::beat_at`] counts the paused time as beats.
+    pub fn timing_points(&self, meter: u32) -> Vec<TimingPoint> {
+        let mut beats: Vec<f64> = self.bpms.iter()
+            .chain(self.stops.iter())
+            .chain(self.delays.iter())
+            .map(|(beat, _)| beat.max(0.0))
+            .collect();
+        beats.sort_by(f64::total_cmp);
+        beats.dedup();
+
+        let mut points: Vec<TimingPoint> = Vec::new();
+        for beat in beats {
+            let stopped: f64 = self.stops.iter().filter(|(stop_beat, _)| *stop_beat == beat).map(|(_, length)| length).sum();
+            let point = TimingPoint {
+                time: self.time_at(beat) + stopped,
+                beat_length: 60_000.0/self.bpm_at(beat),
+                meter,
+                uninherited: true,
+                kiai: false,
+            };
+            let paused = stopped > 0.0 || self.delays.iter().any(|(delay_beat, _)| *delay_beat == beat);
+            let unchanged = points.last().is_some_and(|last| last.beat_length == point.beat_length);
+            if paused || !unchanged {
+                points.push(point);
+            }
+        }
+        points
+    }
+}